@@ -0,0 +1,145 @@
+//! Compares the search kernel against the level-by-level scan it replaced.
+//!
+//! Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use sparkyscrape::kernel::{Kernel, Metric};
+
+/// Values per color channel in the default DCT fingerprint.
+const BLOCK: usize = 16;
+const K: usize = 5;
+
+/// A xorshift generator, so every run searches the same made-up database.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// The distance the scan used: weights recomputed and a square root per
+/// channel on every call.
+fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let n = a.len();
+    let mut acc = 0f32;
+    for (i, (a, b)) in a.iter().zip(b).enumerate() {
+        acc += ((n - i) as f32) / (n as f32) * (a - b).powi(2)
+    }
+    acc.sqrt()
+}
+
+fn dct_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.chunks_exact(BLOCK)
+        .zip(b.chunks_exact(BLOCK))
+        .map(|(a, b)| euclidean_distance(a, b))
+        .sum::<f32>()
+        / 3f32
+}
+
+fn hamming_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+}
+
+/// Every distance collected into a `Vec`, then fully sorted.
+fn scan(
+    levels: &[(String, Vec<f32>)],
+    query: &[f32],
+    distance: fn(&[f32], &[f32]) -> f32,
+) -> Vec<(String, f32)> {
+    let mut guesses = levels
+        .par_iter()
+        .map(|(name, values)| (name, distance(values, query)))
+        .collect::<Vec<_>>();
+
+    guesses.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+    guesses
+        .into_iter()
+        .take(K)
+        .map(|(name, distance)| (name.to_owned(), distance))
+        .collect()
+}
+
+fn levels(count: usize, dim: usize, binary: bool, rng: &mut Rng) -> Vec<(String, Vec<f32>)> {
+    (0..count)
+        .map(|i| {
+            let values = (0..dim)
+                .map(|_| {
+                    let value = rng.next();
+                    if binary {
+                        value.round()
+                    } else {
+                        value * 2000f32 - 1000f32
+                    }
+                })
+                .collect();
+            (format!("level {i}"), values)
+        })
+        .collect()
+}
+
+fn kernel(levels: &[(String, Vec<f32>)], metric: Metric, weights: &[f32], scale: f32) -> Kernel {
+    Kernel::new(
+        metric,
+        weights,
+        scale,
+        levels
+            .iter()
+            .map(|(name, values)| (name.to_owned(), vec![values.as_slice()])),
+    )
+}
+
+fn bench_dct(c: &mut Criterion) {
+    let mut rng = Rng(0x5eed);
+    let weights = (0..3 * BLOCK)
+        .map(|i| (BLOCK - i % BLOCK) as f32 / BLOCK as f32)
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("dct");
+    for count in [1_000, 10_000, 50_000] {
+        let levels = levels(count, 3 * BLOCK, false, &mut rng);
+        let query = levels[count / 2].1.clone();
+        let kernel = kernel(
+            &levels,
+            Metric::BlockEuclidean { block: BLOCK },
+            &weights,
+            1f32 / 3f32,
+        );
+
+        group.bench_with_input(BenchmarkId::new("scan", count), &query, |b, query| {
+            b.iter(|| scan(&levels, black_box(query), dct_distance))
+        });
+        group.bench_with_input(BenchmarkId::new("kernel", count), &query, |b, query| {
+            b.iter(|| kernel.search(black_box(query), K))
+        });
+    }
+    group.finish();
+}
+
+fn bench_phash(c: &mut Criterion) {
+    let mut rng = Rng(0x5eed);
+    let dim = 8 * 8;
+
+    let mut group = c.benchmark_group("phash");
+    for count in [1_000, 10_000, 50_000] {
+        let levels = levels(count, dim, true, &mut rng);
+        let query = levels[count / 2].1.clone();
+        let kernel = kernel(&levels, Metric::Manhattan, &vec![1f32; dim], 1f32);
+
+        group.bench_with_input(BenchmarkId::new("scan", count), &query, |b, query| {
+            b.iter(|| scan(&levels, black_box(query), hamming_distance))
+        });
+        group.bench_with_input(BenchmarkId::new("kernel", count), &query, |b, query| {
+            b.iter(|| kernel.search(black_box(query), K))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_dct, bench_phash);
+criterion_main!(benches);
@@ -10,11 +10,15 @@ import {
   setChannel,
   setChannelGame,
   setChannelGameData,
+  setChannelGameFailed,
   setChannelGameTimeout,
   setChannelGameWin,
 } from './api/channels';
 import Channel from './views/Channel';
 import Guess from './views/Guess';
+import { GuessConfidence } from './components/Confidence';
+import { RankedGuess } from './components/RunnersUp';
+import { DifficultyMatch } from './components/Elsewhere';
 
 export { Link as ReactRouterLink } from 'react-router-dom';
 
@@ -42,25 +46,45 @@ const Layout = () => {
     function onGuessStart({
       channel_id: id,
       difficulty,
+      difficulty_known,
     }: {
       channel_id: string;
       difficulty: number;
+      difficulty_known: boolean;
     }) {
-      dispatch(setChannelGame({ id, game: { downloaded: false, difficulty } }));
+      dispatch(
+        setChannelGame({
+          id,
+          game: {
+            downloaded: false,
+            difficulty,
+            difficulty_known,
+            runners_up: [],
+          },
+        })
+      );
     }
 
     function onGuessData({
       channel_id: id,
-      guess,
+      difficulty,
+      guesses,
+      confidence,
+      elsewhere,
     }: {
       channel_id: string;
-      guess?: [string, number];
+      difficulty: number;
+      guesses: RankedGuess[];
+      confidence?: GuessConfidence;
+      elsewhere?: DifficultyMatch;
     }) {
       dispatch(
         setChannelGameData({
           id,
-          guess: guess?.[0],
-          distance: guess?.[1],
+          difficulty,
+          guesses,
+          confidence: confidence ?? undefined,
+          elsewhere: elsewhere ?? undefined,
         })
       );
     }
@@ -81,6 +105,16 @@ const Layout = () => {
       dispatch(setChannelGameTimeout({ id }));
     }
 
+    function onGuessFailed({
+      channel_id: id,
+      reason,
+    }: {
+      channel_id: string;
+      reason: string;
+    }) {
+      dispatch(setChannelGameFailed({ id, reason }));
+    }
+
     function onDisconnect() {
       dispatch(setConnected(false));
     }
@@ -90,6 +124,7 @@ const Layout = () => {
     socket.on('guess/data', onGuessData);
     socket.on('guess/win', onGuessWin);
     socket.on('guess/timeout', onGuessTimeout);
+    socket.on('guess/failed', onGuessFailed);
     socket.on('disconnect', onDisconnect);
 
     return () => {
@@ -98,6 +133,7 @@ const Layout = () => {
       socket.off('guess/data', onGuessData);
       socket.off('guess/win', onGuessWin);
       socket.off('guess/timeout', onGuessTimeout);
+      socket.off('guess/failed', onGuessFailed);
       socket.off('disconnect', onDisconnect);
     };
   }, [dispatch]);
@@ -1,5 +1,8 @@
 import { PayloadAction, createSlice } from '@reduxjs/toolkit';
 import { RootState } from './store';
+import { GuessConfidence } from '../components/Confidence';
+import { RankedGuess } from '../components/RunnersUp';
+import { DifficultyMatch } from '../components/Elsewhere';
 
 export type ChannelState = {
   id: string;
@@ -10,9 +13,12 @@ export type ChannelState = {
 
 export type ChannelStateGame = {
   difficulty: number;
+  difficulty_known: boolean;
   downloaded: boolean;
   guess?: string;
-  distance?: number;
+  runners_up: RankedGuess[];
+  confidence?: GuessConfidence;
+  elsewhere?: DifficultyMatch;
   result?: ChannelStateGameResult;
 };
 
@@ -20,7 +26,8 @@ export type ChannelStateGameResult =
   | {
       type: 'timeout';
     }
-  | { type: 'win'; answer: string; incorrect: boolean };
+  | { type: 'win'; answer: string; incorrect: boolean }
+  | { type: 'failed'; reason: string };
 
 const MAX_PAST_GAMES = 25;
 
@@ -55,15 +62,20 @@ const slice = createSlice({
       state,
       action: PayloadAction<{
         id: string;
-        guess?: string;
-        distance?: number;
+        difficulty: number;
+        guesses: RankedGuess[];
+        confidence?: GuessConfidence;
+        elsewhere?: DifficultyMatch;
       }>
     ) => {
       const game = state[action.payload.id]?.game;
       if (game) {
         game.downloaded = true;
-        game.guess = action.payload.guess;
-        game.distance = action.payload.distance;
+        game.difficulty = action.payload.difficulty;
+        game.guess = action.payload.guesses[0]?.name;
+        game.runners_up = action.payload.guesses.slice(1);
+        game.confidence = action.payload.confidence;
+        game.elsewhere = action.payload.elsewhere;
       }
     },
 
@@ -85,6 +97,18 @@ const slice = createSlice({
         game.result = { type: 'timeout' };
       }
     },
+
+    setChannelGameFailed: (
+      state,
+      {
+        payload: { id, reason },
+      }: PayloadAction<{ id: string; reason: string }>
+    ) => {
+      const game = state[id]?.game;
+      if (game) {
+        game.result = { type: 'failed', reason };
+      }
+    },
   },
 });
 
@@ -99,15 +123,25 @@ export const selectChannelGame = (id: string) => (state: RootState) =>
 export const selectChannelGameDifficulty = (id: string) => (state: RootState) =>
   state.channels[id]?.game?.difficulty;
 
+export const selectChannelGameDifficultyKnown =
+  (id: string) => (state: RootState) =>
+    state.channels[id]?.game?.difficulty_known;
+
+export const selectChannelGameElsewhere = (id: string) => (state: RootState) =>
+  state.channels[id]?.game?.elsewhere;
+
 export const selectChannelGameDownloaded = (id: string) => (state: RootState) =>
   state.channels[id]?.game?.downloaded;
 
 export const selectChannelGameGuess = (id: string) => (state: RootState) =>
   state.channels[id]?.game?.guess;
 
-export const selectChannelGameGuessDistance =
+export const selectChannelGameRunnersUp = (id: string) => (state: RootState) =>
+  state.channels[id]?.game?.runners_up;
+
+export const selectChannelGameGuessConfidence =
   (id: string) => (state: RootState) =>
-    state.channels[id]?.game?.distance;
+    state.channels[id]?.game?.confidence;
 
 export const selectChannelGameResult = (id: string) => (state: RootState) =>
   state.channels[id]?.game?.result;
@@ -121,6 +155,7 @@ export const {
   setChannelGameData,
   setChannelGameWin,
   setChannelGameTimeout,
+  setChannelGameFailed,
 } = slice.actions;
 
 export default slice;
@@ -0,0 +1,41 @@
+import { Tag } from '@chakra-ui/react';
+import { ComponentProps } from 'react';
+
+export type GuessConfidence = {
+  distance: number;
+  ratio: number;
+  margin: number | null;
+  threshold: number | null;
+  score: number;
+  known: boolean;
+};
+
+const confidenceColor = (score: number) =>
+  score >= 0.5 ? 'green' : score >= 0.2 ? 'yellow' : 'red';
+
+const Confidence = ({
+  confidence,
+  ...props
+}: { confidence: GuessConfidence } & ComponentProps<typeof Tag>) => {
+  const title = `Distance ${confidence.distance.toFixed(2)}${
+    confidence.threshold !== null
+      ? `, known within ${confidence.threshold.toFixed(2)}`
+      : ''
+  }`;
+
+  return confidence.known ? (
+    <Tag
+      colorScheme={confidenceColor(confidence.score)}
+      title={title}
+      {...props}
+    >
+      {Math.round(confidence.score * 100)}% confident
+    </Tag>
+  ) : (
+    <Tag colorScheme="orange" title={title} {...props}>
+      Probably a new level
+    </Tag>
+  );
+};
+
+export default Confidence;
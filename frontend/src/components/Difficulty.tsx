@@ -6,11 +6,21 @@ export const DIFFICULTY_NAMES = ['Easy', 'Medium', 'Hard', 'Legendary'];
 
 const Difficulty = ({
   difficulty,
+  unknown,
   ...props
-}: { difficulty: number } & ComponentProps<typeof Tag>) => {
+}: { difficulty: number; unknown?: boolean } & ComponentProps<typeof Tag>) => {
   return (
-    <Tag colorScheme={DIFFICULTY_COLORS[difficulty] ?? 'green'} {...props}>
+    <Tag
+      colorScheme={DIFFICULTY_COLORS[difficulty] ?? 'green'}
+      title={
+        unknown
+          ? "Sparky didn't say, this is where the closest match was"
+          : undefined
+      }
+      {...props}
+    >
       {DIFFICULTY_NAMES[difficulty] ?? 'Easy'}
+      {unknown && '?'}
     </Tag>
   );
 };
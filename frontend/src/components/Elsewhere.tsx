@@ -0,0 +1,33 @@
+import { Flex, Text } from '@chakra-ui/react';
+import Difficulty from './Difficulty';
+import LevelName from './LevelName';
+import { GuessConfidence } from './Confidence';
+import { RankedGuess } from './RunnersUp';
+
+export type DifficultyMatch = {
+  difficulty: number;
+  guess: RankedGuess;
+  confidence: GuessConfidence;
+};
+
+const Elsewhere = ({ match }: { match?: DifficultyMatch }) => {
+  if (!match) return null;
+
+  return (
+    <Flex
+      direction="column"
+      alignItems="center"
+      gap="2"
+      title={`Distance ${match.guess.distance.toFixed(2)}, ${Math.round(
+        match.confidence.score * 100
+      )}% confident`}
+    >
+      <Text fontSize="sm" color="gray.400">
+        Closer match in <Difficulty difficulty={match.difficulty} size="sm" />
+      </Text>
+      <LevelName name={match.guess.name} size="md" copyable />
+    </Flex>
+  );
+};
+
+export default Elsewhere;
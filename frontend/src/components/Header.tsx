@@ -29,7 +29,10 @@ const Header = () => {
                 !channel.game?.result && (
                   <>
                     {' '}
-                    <Difficulty difficulty={channel.game.difficulty} />
+                    <Difficulty
+                      difficulty={channel.game.difficulty}
+                      unknown={channel.game.difficulty_known === false}
+                    />
                   </>
                 )}
             </Link>
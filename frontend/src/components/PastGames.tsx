@@ -12,6 +12,7 @@ import {
 import { useSelector } from 'react-redux';
 import { selectChannelPastGames } from '../api/channels';
 import Difficulty from './Difficulty';
+import Confidence from './Confidence';
 
 const PastGames = ({ id }: { id: string }) => {
   const past_games = useSelector(selectChannelPastGames(id!));
@@ -25,14 +26,17 @@ const PastGames = ({ id }: { id: string }) => {
             <Th>My guess</Th>
             <Th>Actual answer</Th>
             <Th>Result</Th>
-            <Th isNumeric>Distance</Th>
+            <Th isNumeric>Confidence</Th>
           </Tr>
         </Thead>
         <Tbody>
           {past_games?.map((game) => (
             <Tr>
               <Td>
-                <Difficulty difficulty={game.difficulty} />
+                <Difficulty
+                  difficulty={game.difficulty}
+                  unknown={game.difficulty_known === false}
+                />
               </Td>
               <Td>
                 {game.guess ? <Code>{game.guess}</Code> : <i>No guess</i>}
@@ -50,6 +54,8 @@ const PastGames = ({ id }: { id: string }) => {
               <Td>
                 {game.result?.type === 'timeout' ? (
                   <Badge>Timed out</Badge>
+                ) : game.result?.type === 'failed' ? (
+                  <Badge colorScheme="orange">Download failed</Badge>
                 ) : (
                   game.result?.type === 'win' && (
                     <>
@@ -65,8 +71,8 @@ const PastGames = ({ id }: { id: string }) => {
                 )}
               </Td>
               <Td isNumeric>
-                {game.distance !== undefined ? (
-                  <>{game.distance.toFixed(2)}</>
+                {game.confidence ? (
+                  <Confidence confidence={game.confidence} />
                 ) : (
                   <i>No guess</i>
                 )}
@@ -0,0 +1,34 @@
+import { Flex, Text, Wrap, WrapItem } from '@chakra-ui/react';
+import LevelName from './LevelName';
+
+export type RankedGuess = {
+  name: string;
+  distance: number;
+};
+
+const RunnersUp = ({ guesses }: { guesses: RankedGuess[] }) => {
+  if (guesses.length === 0) return null;
+
+  return (
+    <Flex direction="column" alignItems="center" gap="2">
+      <Text fontSize="sm" color="gray.400">
+        Runners-up
+      </Text>
+      <Wrap justify="center">
+        {guesses.map((guess, i) => (
+          <WrapItem
+            key={guess.name}
+            title={`Distance ${guess.distance.toFixed(2)}`}
+          >
+            <Text mr="1" fontSize="sm" color="gray.500">
+              #{i + 2}
+            </Text>
+            <LevelName name={guess.name} size="md" copyable />
+          </WrapItem>
+        ))}
+      </Wrap>
+    </Flex>
+  );
+};
+
+export default RunnersUp;
@@ -16,15 +16,21 @@ import { useParams } from 'react-router-dom';
 import { useScrollbarWidth, useWindowSize } from 'react-use';
 import {
   selectChannelGameDifficulty,
+  selectChannelGameDifficultyKnown,
   selectChannelGameDownloaded,
+  selectChannelGameElsewhere,
   selectChannelGameGuess,
-  selectChannelGameGuessDistance,
+  selectChannelGameGuessConfidence,
   selectChannelGameResult,
+  selectChannelGameRunnersUp,
   selectChannelName,
 } from '../api/channels';
 import LevelName from '../components/LevelName';
 import LevelImage from '../components/LevelImage';
 import Difficulty from '../components/Difficulty';
+import Confidence from '../components/Confidence';
+import RunnersUp from '../components/RunnersUp';
+import Elsewhere from '../components/Elsewhere';
 import PastGames from '../components/PastGames';
 import { createPortal } from 'react-dom';
 
@@ -35,10 +41,14 @@ const Channel = () => {
 
   const name = useSelector(selectChannelName(id!));
   const difficulty = useSelector(selectChannelGameDifficulty(id!));
+  const difficultyKnown = useSelector(selectChannelGameDifficultyKnown(id!));
   const downloaded = useSelector(selectChannelGameDownloaded(id!));
   const guess = useSelector(selectChannelGameGuess(id!));
-  const distance = useSelector(selectChannelGameGuessDistance(id!));
+  const confidence = useSelector(selectChannelGameGuessConfidence(id!));
+  const runnersUp = useSelector(selectChannelGameRunnersUp(id!));
+  const elsewhere = useSelector(selectChannelGameElsewhere(id!));
   const result = useSelector(selectChannelGameResult(id!));
+  const failed = result?.type === 'failed';
 
   const [correct, setCorrect] = useState<number | undefined>(undefined);
   useEffect(() => {
@@ -77,7 +87,10 @@ const Channel = () => {
                 justifyContent="space-between"
               >
                 <Heading size="md">Guessing</Heading>
-                <Difficulty difficulty={difficulty} />
+                <Difficulty
+                  difficulty={difficulty}
+                  unknown={difficultyKnown === false}
+                />
               </Flex>
             </GridItem>
             <GridItem>
@@ -87,15 +100,15 @@ const Channel = () => {
                 justifyContent="space-between"
               >
                 <Heading size="md">My guess</Heading>
-                {distance !== undefined && <Text>{distance.toFixed(2)}</Text>}
+                {confidence && <Confidence confidence={confidence} />}
               </Flex>
             </GridItem>
             <GridItem>
               <LevelImage
                 difficulty={difficulty}
                 current={id}
-                fallback={!downloaded}
-                message="Downloading..."
+                fallback={!downloaded || failed}
+                message={failed ? 'Download failed.' : 'Downloading...'}
               />
             </GridItem>
             <GridItem>
@@ -103,13 +116,15 @@ const Channel = () => {
                 key={guess}
                 difficulty={difficulty}
                 name={guess}
-                fallback={!downloaded}
-                message="Making a guess..."
+                fallback={!downloaded || failed}
+                message={failed ? 'No guess.' : 'Making a guess...'}
               />
             </GridItem>
             <GridItem display="flex" justifyContent="center">
               {result?.type === 'timeout' ? (
                 <Text>Timed out, no answer available</Text>
+              ) : result?.type === 'failed' ? (
+                <Text color="red.300">{result.reason}</Text>
               ) : result?.type === 'win' ? (
                 <LevelName
                   name={result.answer}
@@ -134,6 +149,12 @@ const Channel = () => {
                 <Text>No guess available</Text>
               )}
             </GridItem>
+            <GridItem>
+              <Elsewhere match={elsewhere} />
+            </GridItem>
+            <GridItem>
+              <RunnersUp guesses={runnersUp ?? []} />
+            </GridItem>
           </Grid>
         )}
         <Heading>Past games</Heading>
@@ -12,18 +12,24 @@ import {
   Radio,
   RadioGroup,
   Stack,
-  Text,
 } from '@chakra-ui/react';
 import { ChangeEvent, useState } from 'react';
 import { DIFFICULTY_STRINGS, getDifficultyString } from '../api/store';
 import Difficulty, { DIFFICULTY_NAMES } from '../components/Difficulty';
+import Confidence, { GuessConfidence } from '../components/Confidence';
+import RunnersUp, { RankedGuess } from '../components/RunnersUp';
 import LevelImage from '../components/LevelImage';
 import LevelName from '../components/LevelName';
 
 const Guess = () => {
   const [loading, setLoading] = useState(false);
   const [difficulty, setDifficulty] = useState('0');
-  const [guess, setGuess] = useState<{ level: string; distance: number }>();
+  const [guess, setGuess] = useState<{
+    level: string;
+    distance: number;
+    guesses: RankedGuess[];
+    confidence: GuessConfidence | null;
+  }>();
   const [file, setFile] = useState<File | null>();
   const [imageData, setImageData] = useState('');
 
@@ -106,7 +112,9 @@ const Guess = () => {
               justifyContent="space-between"
             >
               <Heading size="md">My guess</Heading>
-              {guess !== undefined && <Text>{guess.distance.toFixed(2)}</Text>}
+              {guess?.confidence && (
+                <Confidence confidence={guess.confidence} />
+              )}
             </Flex>
           </GridItem>
           <GridItem>
@@ -130,6 +138,10 @@ const Guess = () => {
           <GridItem display="flex" justifyContent="center">
             {guess && <LevelName name={guess.level} copyable />}
           </GridItem>
+          <GridItem />
+          <GridItem>
+            {guess && <RunnersUp guesses={guess.guesses.slice(1)} />}
+          </GridItem>
         </Grid>
       </Stack>
     </Container>
@@ -0,0 +1,214 @@
+use std::fmt::Display;
+
+use colored::Colorize;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, GenericImageView, Rgba,
+    RgbaImage,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    corpus::CorpusImage,
+    eval::{self, Distances, Query, Report},
+    level::{Coefficients, FingerprintKind, LevelDifficulty, LevelSet, SampleMatch},
+};
+
+/// A perturbation like the ones real screenshots go through between Sparky
+/// rendering a level and us downloading it.
+#[derive(Debug, Clone, Copy)]
+pub enum Augmentation {
+    /// re-encode as JPEG with this quality
+    Jpeg(u8),
+    /// trim this fraction of the width and height from every edge
+    Crop(f32),
+    /// shift the picture by this many pixels, filling the gap with black
+    Offset(i32, i32),
+    /// downscale by this factor, then scale back up to the original size
+    Rescale(f32),
+    /// add this much to every channel
+    Brightness(i32),
+    /// adjust contrast by this percentage
+    Contrast(f32),
+    /// cover this fraction of the height with a translucent bar along the
+    /// bottom, like a caption or a timestamp
+    Overlay(f32),
+}
+
+impl Augmentation {
+    pub const STANDARD: [Augmentation; 14] = [
+        Self::Jpeg(75),
+        Self::Jpeg(30),
+        Self::Jpeg(10),
+        Self::Crop(0.02),
+        Self::Crop(0.05),
+        Self::Offset(4, 0),
+        Self::Offset(8, 8),
+        Self::Rescale(0.75),
+        Self::Rescale(0.5),
+        Self::Brightness(25),
+        Self::Brightness(-25),
+        Self::Contrast(20.0),
+        Self::Contrast(-20.0),
+        Self::Overlay(0.15),
+    ];
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let (width, height) = image.dimensions();
+
+        match *self {
+            Self::Jpeg(quality) => {
+                let mut data = vec![];
+                JpegEncoder::new_with_quality(&mut data, quality)
+                    .encode_image(&image.to_rgb8())
+                    .unwrap();
+                image::load_from_memory(&data).unwrap()
+            }
+            Self::Crop(fraction) => {
+                let x = (width as f32 * fraction) as u32;
+                let y = (height as f32 * fraction) as u32;
+                image.crop_imm(x, y, width - 2 * x, height - 2 * y)
+            }
+            Self::Offset(dx, dy) => {
+                let mut shifted = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+                image::imageops::overlay(&mut shifted, &image.to_rgba8(), dx as i64, dy as i64);
+                shifted.into()
+            }
+            Self::Rescale(factor) => image
+                .resize_exact(
+                    ((width as f32 * factor) as u32).max(1),
+                    ((height as f32 * factor) as u32).max(1),
+                    FilterType::Triangle,
+                )
+                .resize_exact(width, height, FilterType::Triangle),
+            Self::Brightness(value) => image.brighten(value),
+            Self::Contrast(value) => image.adjust_contrast(value),
+            Self::Overlay(fraction) => {
+                let bar_height = ((height as f32 * fraction) as u32).max(1);
+                let bar = RgbaImage::from_pixel(width, bar_height, Rgba([255, 255, 255, 160]));
+
+                let mut covered = image.to_rgba8();
+                image::imageops::overlay(&mut covered, &bar, 0, (height - bar_height) as i64);
+                covered.into()
+            }
+        }
+    }
+}
+
+impl Display for Augmentation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Jpeg(quality) => write!(f, "jpeg q{quality}"),
+            Self::Crop(fraction) => write!(f, "crop {:.0}%", fraction * 100f32),
+            Self::Offset(dx, dy) => write!(f, "offset {dx},{dy}"),
+            Self::Rescale(factor) => write!(f, "rescale {factor}x"),
+            Self::Brightness(value) => write!(f, "brightness {value:+}"),
+            Self::Contrast(value) => write!(f, "contrast {value:+}"),
+            Self::Overlay(fraction) => write!(f, "overlay {:.0}%", fraction * 100f32),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RobustnessReport {
+    pub augmentation: String,
+    /// distances from each clean image's fingerprint to its perturbed copy's
+    pub drift: Distances,
+    /// how the perturbed copies fared against the database
+    pub matching: Report,
+}
+
+impl RobustnessReport {
+    pub fn print(&self, difficulty: LevelDifficulty) {
+        println!(
+            "  {:<15} drift median {:.2}  p90 {:.2}  max {:.2}  top-1 {}  top-5 {:.1}%",
+            self.augmentation,
+            self.drift.median,
+            self.drift.p90,
+            self.drift.max,
+            format!("{:.1}%", self.matching.top1 * 100f32).bold(),
+            self.matching.top5 * 100f32,
+        );
+
+        for confusion in &self.matching.confusions {
+            println!(
+                "    {} {} {} (dist {:.2})",
+                difficulty.colorize(confusion.name.as_str()),
+                "->".red(),
+                difficulty.colorize(confusion.guess.as_str()),
+                confusion.distance,
+            );
+        }
+    }
+}
+
+/// Perturbs every image with each of `augmentations`, then measures how far
+/// that moves its fingerprint and whether it still matches the right level.
+///
+/// Images that fail to decode are left out.
+pub fn evaluate(
+    difficulty: LevelDifficulty,
+    levels: &LevelSet,
+    images: &[CorpusImage],
+    augmentations: &[Augmentation],
+    mode: SampleMatch,
+) -> Vec<RobustnessReport> {
+    let fingerprint = levels.fingerprint.fingerprint();
+    let measure = levels.measure();
+    let decoded = images
+        .par_iter()
+        .filter_map(|image| {
+            let decoded = image::open(&image.path).ok()?;
+            let clean = fingerprint.compute(&decoded);
+            Some((image.name.as_str(), decoded, clean))
+        })
+        .collect::<Vec<_>>();
+
+    augmentations
+        .iter()
+        .map(|augmentation| {
+            let perturbed = decoded
+                .par_iter()
+                .map(|(name, image, clean)| {
+                    let coefficients = fingerprint.compute(&augmentation.apply(image));
+                    let drift = measure.distance(clean, &coefficients);
+                    (name.to_string(), coefficients, drift)
+                })
+                .collect::<Vec<(String, Coefficients, f32)>>();
+
+            let drift = Distances::new(perturbed.iter().map(|(_, _, drift)| *drift).collect());
+            let queries = perturbed
+                .into_iter()
+                .map(|(name, coefficients, _)| Query { name, coefficients })
+                .collect::<Vec<_>>();
+
+            RobustnessReport {
+                augmentation: augmentation.to_string(),
+                drift,
+                matching: eval::evaluate(difficulty, levels, &queries, mode, false),
+            }
+        })
+        .collect()
+}
+
+/// Perturbs every image with each of `augmentations`, labelled with the level
+/// it's of. Images that fail to decode are left out.
+pub fn queries(
+    fingerprint: FingerprintKind,
+    images: &[CorpusImage],
+    augmentations: &[Augmentation],
+) -> Vec<Query> {
+    let fingerprint = fingerprint.fingerprint();
+    images
+        .par_iter()
+        .flat_map_iter(|image| {
+            let decoded = image::open(&image.path).ok();
+            augmentations.iter().filter_map(move |augmentation| {
+                Some(Query {
+                    name: image.name.to_owned(),
+                    coefficients: fingerprint.compute(&augmentation.apply(decoded.as_ref()?)),
+                })
+            })
+        })
+        .collect()
+}
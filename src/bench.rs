@@ -0,0 +1,197 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    level::{Coefficients, Level, LevelSet, SampleMatch},
+    search::{self, Guess},
+};
+
+/// A xorshift generator, so benchmarks are repeatable without pulling in a
+/// random number crate.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A value in `-1..1`.
+    fn signed(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 23) as f32 - 1f32
+    }
+}
+
+fn all_samples(levels: &LevelSet) -> Vec<&Coefficients> {
+    levels
+        .values()
+        .flat_map(|level| level.samples.iter())
+        .collect()
+}
+
+/// The standard deviation of each value across every sample, so noise can be
+/// scaled to how much fingerprints actually vary.
+fn spread(samples: &[&Coefficients]) -> Vec<f32> {
+    let mean = Coefficients::mean(samples.iter().copied());
+    (0..mean.0.len())
+        .map(|i| {
+            let variance = samples
+                .iter()
+                .map(|sample| (sample.0[i] - mean.0[i]).powi(2))
+                .sum::<f32>()
+                / samples.len() as f32;
+            variance.sqrt()
+        })
+        .collect()
+}
+
+/// A random sample with every value nudged by up to `noise` standard
+/// deviations.
+fn perturbed(samples: &[&Coefficients], spread: &[f32], noise: f32, rng: &mut Rng) -> Coefficients {
+    let sample = samples[rng.below(samples.len())];
+    Coefficients(
+        sample
+            .0
+            .iter()
+            .zip(spread)
+            .map(|(value, spread)| value + rng.signed() * noise * spread)
+            .collect(),
+    )
+}
+
+/// Pads the set with made-up levels until it holds `count`, each a perturbed
+/// copy of a real sample, to see how search scales past the real database.
+pub fn pad(levels: &mut LevelSet, count: usize, rng: &mut Rng) {
+    let samples = all_samples(levels).into_iter().cloned().collect::<Vec<_>>();
+    let samples = samples.iter().collect::<Vec<_>>();
+    if samples.is_empty() {
+        return;
+    }
+
+    let spread = spread(&samples);
+    let difficulty = levels.values().next().unwrap().difficulty;
+
+    let mut i = 0;
+    while levels.len() < count {
+        let name = format!("synthetic {i}");
+        let coefficients = perturbed(&samples, &spread, 1f32, rng);
+        levels.insert(name.to_owned(), Level::new(name, difficulty, coefficients));
+        i += 1;
+    }
+}
+
+/// Perturbed copies of random samples to look up.
+pub fn queries(levels: &LevelSet, count: usize, noise: f32, rng: &mut Rng) -> Vec<Coefficients> {
+    let samples = all_samples(levels);
+    if samples.is_empty() {
+        return vec![];
+    }
+
+    let spread = spread(&samples);
+    (0..count)
+        .map(|_| perturbed(&samples, &spread, noise, rng))
+        .collect()
+}
+
+/// How one way of searching fared.
+pub struct Timing {
+    /// how long it took to set up
+    pub build: Duration,
+    /// how long every query took together
+    pub search: Duration,
+    /// how many of the full scan's top-k levels it found too
+    pub recall: f32,
+    /// how often its best guess was as close as the full scan's
+    pub top1: f32,
+}
+
+pub struct Benchmark {
+    pub scan: Duration,
+    pub kernel: Option<Timing>,
+    pub index: Option<Timing>,
+}
+
+/// Compares guesses from some other search against the full scan's.
+fn timing(build: Duration, search: Duration, scan: &[Vec<Guess>], other: &[Vec<Guess>]) -> Timing {
+    let mut found = 0;
+    let mut wanted = 0;
+    let mut top1 = 0;
+    for (scan, other) in scan.iter().zip(other) {
+        wanted += scan.len();
+        found += scan
+            .iter()
+            .filter(|guess| other.iter().any(|o| o.name == guess.name))
+            .count();
+
+        // ties can come out in either order, and the kernel rounds a little
+        // differently, so compare distances
+        if let (Some(scan), Some(other)) = (scan.first(), other.first()) {
+            if (scan.distance - other.distance).abs() <= 1e-3 * scan.distance.max(1f32) {
+                top1 += 1;
+            }
+        }
+    }
+
+    Timing {
+        build,
+        search,
+        recall: found as f32 / wanted.max(1) as f32,
+        top1: top1 as f32 / scan.len().max(1) as f32,
+    }
+}
+
+/// Times every query through a level-by-level scan, the kernel and the index,
+/// where the fingerprint allows them.
+pub fn run(
+    levels: &mut LevelSet,
+    queries: &[Coefficients],
+    k: usize,
+    mode: SampleMatch,
+) -> Benchmark {
+    let time =
+        |levels: &LevelSet,
+         search: fn(&LevelSet, &Coefficients, usize, SampleMatch) -> search::SearchResult| {
+            let start = Instant::now();
+            let guesses = queries
+                .iter()
+                .map(|query| search(levels, query, k, mode).guesses)
+                .collect::<Vec<_>>();
+            (start.elapsed(), guesses)
+        };
+
+    levels.index = None;
+    levels.kernel = None;
+    let (scan_time, scan) = time(levels, search::scan);
+
+    let start = Instant::now();
+    levels.build_kernel(mode);
+    let build = start.elapsed();
+    let kernel = levels.kernel.is_some().then(|| {
+        let (search, guesses) = time(levels, search::search_exact);
+        timing(build, search, &scan, &guesses)
+    });
+    levels.kernel = None;
+
+    let start = Instant::now();
+    levels.build_index(mode);
+    let build = start.elapsed();
+    let index = levels.index.is_some().then(|| {
+        let (search, guesses) = time(levels, search::search);
+        timing(build, search, &scan, &guesses)
+    });
+
+    Benchmark {
+        scan: scan_time,
+        kernel,
+        index,
+    }
+}
@@ -0,0 +1,123 @@
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::level::{Coefficients, LevelSet, SampleMatch};
+
+/// The most samples `learn_threshold` matches against the rest of the set, so
+/// calibrating stays quick on large databases.
+const MAX_CALIBRATION_SAMPLES: usize = 2000;
+
+/// How sure we are that the closest level is the one in a screenshot.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Confidence {
+    /// the distance to the closest level
+    pub distance: f32,
+    /// the closest distance over the second-closest; near 1 when the two are
+    /// hard to tell apart
+    pub ratio: f32,
+    /// the second-closest distance minus the closest
+    pub margin: f32,
+    /// the threshold the distance was held against, if one was learned
+    pub threshold: Option<f32>,
+    /// how sure we are, from 0 to 1
+    pub score: f32,
+    /// whether the closest level is within the threshold; if not, this is
+    /// probably a level we've never seen
+    pub known: bool,
+}
+
+impl Confidence {
+    /// Rates a guess from the distances to every level, closest first. `None`
+    /// if there were no levels to guess from.
+    pub fn new(distances: &[f32], threshold: Option<f32>) -> Option<Self> {
+        let distance = *distances.first()?;
+        let second = distances.get(1).copied().unwrap_or(f32::INFINITY);
+
+        // both distances are 0 when two levels share a sample
+        let ratio = if second > 0f32 {
+            distance / second
+        } else {
+            1f32
+        };
+
+        // a match at the threshold is a coin toss, twice as far is hopeless
+        let closeness = match threshold {
+            Some(threshold) if threshold > 0f32 => 1f32 - distance / (2f32 * threshold),
+            Some(_) if distance > 0f32 => 0f32,
+            _ => 1f32,
+        };
+
+        Some(Self {
+            distance,
+            ratio,
+            margin: second - distance,
+            threshold,
+            score: ((1f32 - ratio) * closeness).clamp(0f32, 1f32),
+            known: !matches!(threshold, Some(threshold) if distance > threshold),
+        })
+    }
+}
+
+/// Learns the distance under which a guess is probably a level we know.
+///
+/// Each sample is matched against the rest of the set twice: against its own
+/// level's other samples, which is how far a repeat screenshot lands, and
+/// against every other level, which is how far the closest wrong level lands
+/// when a screenshot is of a level we don't have. The threshold is the one
+/// that best separates the two. `None` if there is only one level.
+pub fn learn_threshold(levels: &LevelSet, mode: SampleMatch) -> Option<f32> {
+    let samples = levels
+        .values()
+        .flat_map(|level| level.samples.iter().map(move |sample| (level, sample)))
+        .collect::<Vec<_>>();
+    let step = samples.len().div_ceil(MAX_CALIBRATION_SAMPLES).max(1);
+    let samples = samples.into_iter().step_by(step).collect::<Vec<_>>();
+
+    // (distance, whether it was to the sample's own level)
+    let measure = levels.measure();
+    let mut distances = samples
+        .par_iter()
+        .flat_map_iter(|(own, sample): &(_, &Coefficients)| {
+            let same = own.distance_excluding(sample, &measure, mode);
+            let other = levels
+                .values()
+                .filter(|level| level.name != own.name)
+                .map(|level| level.distance_to(sample, &measure, mode))
+                .reduce(f32::min);
+
+            [same.map(|d| (d, true)), other.map(|d| (d, false))]
+                .into_iter()
+                .flatten()
+        })
+        .collect::<Vec<_>>();
+
+    if !distances.iter().any(|(_, same)| !same) {
+        return None;
+    }
+
+    distances.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    // sweep the threshold upwards, starting below every distance, where every
+    // own-level distance is on the wrong side of it
+    let mut errors = distances.iter().filter(|(_, same)| *same).count();
+    let mut best = (errors, 0);
+    for (i, (distance, same)) in distances.iter().enumerate() {
+        if *same {
+            errors -= 1;
+        } else {
+            errors += 1;
+        }
+
+        // only split between distinct distances
+        let next = distances.get(i + 1).map(|(next, _)| *next);
+        if next != Some(*distance) && errors < best.0 {
+            best = (errors, i + 1);
+        }
+    }
+
+    Some(match best.1 {
+        0 => distances[0].0 / 2f32,
+        i if i == distances.len() => distances[i - 1].0,
+        i => (distances[i - 1].0 + distances[i].0) / 2f32,
+    })
+}
@@ -0,0 +1,58 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::level::{Coefficients, FingerprintKind, LevelDifficulty};
+
+/// A labelled level image, laid out like the handler saves them:
+/// `<root>/<difficulty>/<name>.png`.
+#[derive(Debug, Clone)]
+pub struct CorpusImage {
+    pub name: String,
+    pub difficulty: LevelDifficulty,
+    pub path: PathBuf,
+}
+
+/// Lists the images of one difficulty under `root`, sorted by name. Names are
+/// lowercased like the handler's answers.
+pub fn list_images(root: &Path, difficulty: LevelDifficulty) -> io::Result<Vec<CorpusImage>> {
+    let dir = root.join(difficulty.directory());
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut images = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        images.push(CorpusImage {
+            name: name.to_lowercase(),
+            difficulty,
+            path,
+        });
+    }
+
+    images.sort_by(|a, b| a.name.cmp(&b.name).then_with(|| a.path.cmp(&b.path)));
+    Ok(images)
+}
+
+/// Reads and fingerprints images in parallel, in the same order.
+pub fn fingerprint_images(
+    images: &[CorpusImage],
+    fingerprint: FingerprintKind,
+) -> Vec<anyhow::Result<Coefficients>> {
+    images
+        .par_iter()
+        .map(|image| fingerprint.compute(&std::fs::read(&image.path)?))
+        .collect()
+}
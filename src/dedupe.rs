@@ -0,0 +1,272 @@
+use std::{cmp::Reverse, collections::HashMap, io::Write, path::Path};
+
+use colored::Colorize;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::level::{normalize_name, LevelDifficulty, LevelSet, SampleMatch};
+
+/// Why two levels look like the same one.
+pub struct Link {
+    pub a: String,
+    pub b: String,
+    /// how far apart their fingerprints are
+    pub distance: f32,
+    /// how many single-character edits turn one name into the other
+    pub edits: usize,
+}
+
+/// Levels that are probably all the same one, linked to each other directly
+/// or through other levels in the cluster.
+pub struct Cluster {
+    /// sorted by name
+    pub names: Vec<String>,
+    pub links: Vec<Link>,
+}
+
+/// A merge to apply, as read from or written to a decisions file.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Decision {
+    pub difficulty: String,
+    /// the name to keep
+    pub into: String,
+    /// the names to fold into it
+    pub merge: Vec<String>,
+}
+
+/// The representative of `i`'s set in a union-find forest, shortening the
+/// path on the way.
+fn root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+/// The digits in a name. Names that differ in them are usually sequels or
+/// numbered parts, not typos.
+fn digits(name: &str) -> String {
+    name.chars().filter(char::is_ascii_digit).collect()
+}
+
+/// Finds groups of levels whose fingerprints are within `max_distance` of each
+/// other or whose names are within `max_edits` edits (and have the same
+/// digits), largest groups first.
+pub fn find_clusters(
+    levels: &LevelSet,
+    max_distance: f32,
+    max_edits: usize,
+    mode: SampleMatch,
+) -> Vec<Cluster> {
+    let measure = levels.measure();
+    let mut names = levels.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    // every pair once; a level is as close to another as its closest sample
+    let links = (0..names.len())
+        .into_par_iter()
+        .flat_map_iter(|i| {
+            let a = &levels[&names[i]];
+            let measure = &measure;
+            names[i + 1..].iter().filter_map(move |name| {
+                let b = &levels[name];
+                let distance = match mode {
+                    SampleMatch::Nearest => b
+                        .samples
+                        .iter()
+                        .map(|sample| a.distance_to(sample, measure, mode))
+                        .fold(f32::INFINITY, f32::min),
+                    SampleMatch::Centroid => a.distance_to(&b.centroid, measure, mode),
+                };
+                let edits = levenshtein::levenshtein(&a.name, &b.name);
+
+                let similar_names = edits <= max_edits && digits(&a.name) == digits(&b.name);
+
+                (distance <= max_distance || similar_names).then(|| Link {
+                    a: a.name.to_owned(),
+                    b: b.name.to_owned(),
+                    distance,
+                    edits,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // union-find over the linked names
+    let index = names
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect::<HashMap<_, _>>();
+    let mut parent = (0..names.len()).collect::<Vec<_>>();
+    for link in &links {
+        let a = root(&mut parent, index[link.a.as_str()]);
+        let b = root(&mut parent, index[link.b.as_str()]);
+        parent[a.max(b)] = a.min(b);
+    }
+
+    let mut clusters: HashMap<usize, Cluster> = HashMap::new();
+    for link in links {
+        let cluster = root(&mut parent, index[link.a.as_str()]);
+        clusters
+            .entry(cluster)
+            .or_insert_with(|| Cluster {
+                names: vec![],
+                links: vec![],
+            })
+            .links
+            .push(link);
+    }
+    for (i, name) in names.iter().enumerate() {
+        if let Some(cluster) = clusters.get_mut(&root(&mut parent, i)) {
+            cluster.names.push(name.to_owned());
+        }
+    }
+
+    let mut clusters = clusters.into_values().collect::<Vec<_>>();
+    clusters.sort_by(|a, b| {
+        b.names
+            .len()
+            .cmp(&a.names.len())
+            .then(a.names.cmp(&b.names))
+    });
+    clusters
+}
+
+impl Cluster {
+    /// The name most worth keeping: the level that was the answer most often,
+    /// then the one with the most samples, then the first by name.
+    pub fn suggested(&self, levels: &LevelSet) -> &str {
+        self.names
+            .iter()
+            .min_by_key(|name| {
+                let level = &levels[*name];
+                Reverse((level.metadata.times_seen, level.samples.len()))
+            })
+            .unwrap()
+    }
+
+    /// Prints the names, how many samples each has and where its image is,
+    /// then what links them.
+    pub fn print(&self, difficulty: LevelDifficulty, levels: &LevelSet) {
+        for (i, name) in self.names.iter().enumerate() {
+            let image = difficulty.image_path(name);
+            println!(
+                "  {} {} ({} samples) {}",
+                format!("{}.", i + 1).bold(),
+                difficulty.colorize(name.as_str()),
+                levels[name].samples.len(),
+                if Path::new(&image).exists() {
+                    image.normal()
+                } else {
+                    "no image".red()
+                }
+            );
+        }
+
+        for link in &self.links {
+            println!(
+                "     {} ~ {} (dist {:.2}, {} edits)",
+                link.a, link.b, link.distance, link.edits
+            );
+        }
+    }
+}
+
+/// Image moves planned by `merge`, made once the merged levels are saved so an
+/// image never ends up under a name the saved database doesn't have.
+#[derive(Debug, Default)]
+pub struct ImageMoves {
+    moves: Vec<(String, String)>,
+}
+
+impl ImageMoves {
+    /// Whether there will be an image at `path` once the moves are made.
+    fn exists(&self, path: &str) -> bool {
+        for (from, to) in self.moves.iter().rev() {
+            if to == path {
+                return true;
+            }
+            if from == path {
+                return false;
+            }
+        }
+        Path::new(path).exists()
+    }
+
+    /// Makes the moves, in the order they were planned.
+    pub fn apply(self) -> std::io::Result<()> {
+        for (from, to) in self.moves {
+            if let Some(dir) = Path::new(&to).parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::rename(from, to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Folds levels into another: their samples and metadata are added to it,
+/// their names become aliases of it, and their images are planned to move to
+/// `levels/<difficulty>/merged/` so a rebuild doesn't bring them back. The kept
+/// level takes over an image if it has none.
+pub fn merge(
+    difficulty: LevelDifficulty,
+    levels: &mut LevelSet,
+    into: &str,
+    others: &[String],
+    moves: &mut ImageMoves,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(levels.contains_key(into), "there is no level named {into}");
+    for (i, name) in others.iter().enumerate() {
+        anyhow::ensure!(
+            name != into && levels.contains_key(name),
+            "there is no other level named {name}"
+        );
+        anyhow::ensure!(
+            !others[..i].contains(name),
+            "{name} is listed more than once"
+        );
+    }
+
+    let merged_dir = format!("levels/{}/merged", difficulty.directory());
+    for name in others {
+        let level = levels.remove(name).unwrap();
+        let kept = levels.get_mut(into).unwrap();
+        for sample in level.samples {
+            kept.add_sample(sample);
+        }
+        kept.metadata.absorb(level.metadata);
+
+        // the old name keeps finding the level, unless another level's name
+        // normalises the same
+        levels.retarget_aliases(name, Some(into));
+        if normalize_name(name) != normalize_name(into) {
+            levels.add_alias(name, into).ok();
+        }
+
+        let image = difficulty.image_path(name);
+        let kept_image = difficulty.image_path(into);
+        if moves.exists(&image) {
+            let to = if moves.exists(&kept_image) {
+                format!("{merged_dir}/{name}.png")
+            } else {
+                kept_image
+            };
+            moves.moves.push((image, to));
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks a question on the terminal and returns the trimmed answer.
+pub fn prompt(question: &str) -> String {
+    print!("{question} ");
+    std::io::stdout().flush().unwrap();
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).unwrap();
+    answer.trim().to_owned()
+}
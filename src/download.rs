@@ -0,0 +1,189 @@
+//! Downloads round screenshots with one client shared by every round: with
+//! timeouts, a few retries, a size limit, and a check that what came back is
+//! an image.
+
+use std::{fmt::Display, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use colored::Colorize;
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DownloadConfig {
+    /// how long to wait to connect, in milliseconds
+    pub connect_timeout_ms: u64,
+    /// how long to wait for each part of the response, in milliseconds
+    pub read_timeout_ms: u64,
+    /// how many more times to try after a failed attempt
+    pub retries: u32,
+    /// how long to wait before the first retry, in milliseconds; it doubles
+    /// after each one
+    pub backoff_ms: u64,
+    /// the largest screenshot accepted, in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: 5000,
+            read_timeout_ms: 10000,
+            retries: 3,
+            backoff_ms: 500,
+            max_bytes: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    /// connecting or reading failed
+    Request(reqwest::Error),
+    /// nothing arrived for longer than the read timeout
+    Timeout,
+    Status(StatusCode),
+    /// the body is bigger than `max_bytes`
+    TooLarge(usize),
+    /// the server says it isn't an image
+    NotAnImage(String),
+    /// the body doesn't start like any image format we can read
+    UnknownFormat,
+}
+
+impl DownloadError {
+    /// Whether trying again might go differently.
+    fn is_transient(&self) -> bool {
+        match self {
+            Self::Request(_) | Self::Timeout => true,
+            Self::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::TooLarge(_) | Self::NotAnImage(_) | Self::UnknownFormat => false,
+        }
+    }
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Request(err) => write!(f, "{}", err),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Status(status) => write!(f, "the server answered {}", status),
+            Self::TooLarge(max_bytes) => write!(f, "bigger than {} bytes", max_bytes),
+            Self::NotAnImage(content_type) => write!(f, "not an image but {}", content_type),
+            Self::UnknownFormat => write!(f, "not an image format we can read"),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout
+        } else {
+            Self::Request(err)
+        }
+    }
+}
+
+/// Checks that a screenshot starts like an image we can decode.
+pub fn check_image(bytes: &[u8]) -> Result<(), DownloadError> {
+    image::guess_format(bytes)
+        .map(|_| ())
+        .map_err(|_| DownloadError::UnknownFormat)
+}
+
+#[derive(Clone)]
+pub struct Downloader {
+    client: reqwest::Client,
+    config: DownloadConfig,
+}
+
+impl Downloader {
+    pub fn new(config: DownloadConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+            .build()?;
+        Ok(Self { client, config })
+    }
+
+    /// Downloads a screenshot, trying again after failures that might not
+    /// happen twice.
+    pub async fn download(&self, url: &str) -> Result<Bytes, DownloadError> {
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+        let mut attempt = 0;
+        loop {
+            match self.try_download(url).await {
+                Err(err) if err.is_transient() && attempt < self.config.retries => {
+                    attempt += 1;
+                    println!(
+                        "{} downloading {} failed ({}), retrying in {:?} ({}/{})",
+                        "warning!".yellow().bold(),
+                        url,
+                        err,
+                        backoff,
+                        attempt,
+                        self.config.retries
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_download(&self, url: &str) -> Result<Bytes, DownloadError> {
+        let read_timeout = Duration::from_millis(self.config.read_timeout_ms);
+        let max_bytes = self.config.max_bytes;
+
+        let mut response = tokio::time::timeout(read_timeout, self.client.get(url).send())
+            .await
+            .map_err(|_| DownloadError::Timeout)??;
+
+        if !response.status().is_success() {
+            return Err(DownloadError::Status(response.status()));
+        }
+
+        // Discord's CDN says what it's serving; anything else saying it's an
+        // error page or the like isn't worth reading
+        if let Some(content_type) = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+        {
+            if !content_type.starts_with("image/")
+                && !content_type.starts_with("application/octet-stream")
+            {
+                return Err(DownloadError::NotAnImage(content_type.to_owned()));
+            }
+        }
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > max_bytes as u64)
+        {
+            return Err(DownloadError::TooLarge(max_bytes));
+        }
+
+        // the length can be missing or wrong, so count as it arrives
+        let mut body = BytesMut::new();
+        while let Some(chunk) = tokio::time::timeout(read_timeout, response.chunk())
+            .await
+            .map_err(|_| DownloadError::Timeout)??
+        {
+            if body.len() + chunk.len() > max_bytes {
+                return Err(DownloadError::TooLarge(max_bytes));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let body = body.freeze();
+        check_image(&body)?;
+        Ok(body)
+    }
+}
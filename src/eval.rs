@@ -0,0 +1,239 @@
+use colored::Colorize;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    corpus::{self, CorpusImage},
+    level::{Coefficients, FingerprintKind, LevelDifficulty, LevelSet, SampleMatch},
+};
+
+/// How many ranks count towards top-k accuracy.
+const TOP_K: usize = 5;
+
+/// A labelled image to look up in the database.
+pub struct Query {
+    pub name: String,
+    pub coefficients: Coefficients,
+}
+
+/// Fingerprints every image to look up, reporting the ones that can't be read.
+pub fn queries(images: Vec<CorpusImage>, fingerprint: FingerprintKind) -> Vec<Query> {
+    let computed = corpus::fingerprint_images(&images, fingerprint);
+
+    let mut queries = vec![];
+    for (image, coefficients) in images.into_iter().zip(computed) {
+        match coefficients {
+            Ok(coefficients) => queries.push(Query {
+                name: image.name,
+                coefficients,
+            }),
+            Err(err) => println!("could not read {}: {:#}", image.path.display(), err),
+        }
+    }
+    queries
+}
+
+/// Summary statistics of a set of distances.
+#[derive(Debug, Default, Serialize)]
+pub struct Distances {
+    pub count: usize,
+    pub min: f32,
+    pub median: f32,
+    pub mean: f32,
+    pub p90: f32,
+    pub max: f32,
+}
+
+impl Distances {
+    pub fn new(mut distances: Vec<f32>) -> Self {
+        if distances.is_empty() {
+            return Self::default();
+        }
+
+        distances.sort_by(f32::total_cmp);
+        let at = |q: f32| distances[((distances.len() - 1) as f32 * q).round() as usize];
+
+        Self {
+            count: distances.len(),
+            min: distances[0],
+            median: at(0.5),
+            mean: distances.iter().sum::<f32>() / distances.len() as f32,
+            p90: at(0.9),
+            max: distances[distances.len() - 1],
+        }
+    }
+
+    fn print(&self, label: &str) {
+        println!(
+            "  {:<9} n={:<5} min {:.2}  median {:.2}  mean {:.2}  p90 {:.2}  max {:.2}",
+            label, self.count, self.min, self.median, self.mean, self.p90, self.max
+        );
+    }
+}
+
+/// A query whose best match was the wrong level.
+#[derive(Debug, Serialize)]
+pub struct Confusion {
+    pub name: String,
+    pub guess: String,
+    pub distance: f32,
+    /// the rank of the right level, if it was in the database at all
+    pub rank: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub difficulty: String,
+    pub fingerprint: String,
+    pub leave_one_out: bool,
+    pub queries: usize,
+    /// leave-one-out queries whose level had no other sample to match
+    pub skipped: usize,
+    pub top1: f32,
+    pub top5: f32,
+    pub mean_reciprocal_rank: f32,
+    /// best-match distances when the best match was right
+    pub correct: Distances,
+    /// best-match distances when the best match was wrong
+    pub incorrect: Distances,
+    pub confusions: Vec<Confusion>,
+}
+
+/// Matches every query against the database and measures how often the right
+/// level comes out on top.
+///
+/// With `leave_one_out`, each query's own sample is removed from its level
+/// first, so only the level's other samples can match it.
+pub fn evaluate(
+    difficulty: LevelDifficulty,
+    levels: &LevelSet,
+    queries: &[Query],
+    mode: SampleMatch,
+    leave_one_out: bool,
+) -> Report {
+    // (rank of the right level, best guess, best distance) per query
+    let measure = levels.measure();
+    let results = queries
+        .par_iter()
+        .filter_map(|query| {
+            let mut ranking = vec![];
+            for level in levels.values() {
+                if leave_one_out && level.name == query.name {
+                    let distance = level.distance_excluding(&query.coefficients, &measure, mode)?;
+                    ranking.push((level.name.as_str(), distance));
+                } else {
+                    ranking.push((
+                        level.name.as_str(),
+                        level.distance_to(&query.coefficients, &measure, mode),
+                    ));
+                }
+            }
+
+            ranking.sort_by(|(_, a), (_, b)| a.total_cmp(b));
+            let rank = ranking
+                .iter()
+                .position(|(name, _)| *name == query.name)
+                .map(|i| i + 1);
+            let (guess, distance) = ranking.first().copied()?;
+
+            Some((query, rank, guess.to_owned(), distance))
+        })
+        .collect::<Vec<_>>();
+
+    let n = results.len().max(1) as f32;
+    let mut correct = vec![];
+    let mut incorrect = vec![];
+    let mut confusions = vec![];
+    let mut top1 = 0;
+    let mut top5 = 0;
+    let mut reciprocal_ranks = 0f32;
+
+    for (query, rank, guess, distance) in &results {
+        if let Some(rank) = rank {
+            reciprocal_ranks += 1f32 / *rank as f32;
+            if *rank <= TOP_K {
+                top5 += 1;
+            }
+        }
+
+        if *rank == Some(1) {
+            top1 += 1;
+            correct.push(*distance);
+        } else {
+            incorrect.push(*distance);
+            confusions.push(Confusion {
+                name: query.name.to_owned(),
+                guess: guess.to_owned(),
+                distance: *distance,
+                rank: *rank,
+            });
+        }
+    }
+
+    confusions.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+
+    Report {
+        difficulty: difficulty.directory().to_owned(),
+        fingerprint: levels.fingerprint.to_string(),
+        leave_one_out,
+        queries: results.len(),
+        skipped: queries.len() - results.len(),
+        top1: top1 as f32 / n,
+        top5: top5 as f32 / n,
+        mean_reciprocal_rank: reciprocal_ranks / n,
+        correct: Distances::new(correct),
+        incorrect: Distances::new(incorrect),
+        confusions,
+    }
+}
+
+impl Report {
+    pub fn print(&self, difficulty: LevelDifficulty) {
+        println!(
+            "{} {} ({}, {} queries, {} skipped)",
+            difficulty,
+            self.fingerprint,
+            if self.leave_one_out {
+                "leave-one-out"
+            } else {
+                "held-out"
+            },
+            self.queries,
+            self.skipped
+        );
+        println!(
+            "  top-1 {}  top-{} {:.1}%  MRR {:.3}",
+            format!("{:.1}%", self.top1 * 100f32).bold(),
+            TOP_K,
+            self.top5 * 100f32,
+            self.mean_reciprocal_rank
+        );
+        // levels with a single sample have nothing left to match once it's
+        // left out, so the accuracy only covers the ones with several
+        if self.skipped > 0 {
+            println!(
+                "  {} {} of {} queries skipped, their level has no other sample; \
+                 the accuracy only covers levels with several samples",
+                "warning!".yellow().bold(),
+                self.skipped,
+                self.queries + self.skipped
+            );
+        }
+        self.correct.print("correct");
+        self.incorrect.print("incorrect");
+
+        for confusion in &self.confusions {
+            println!(
+                "  {} {} {} (dist {:.2}, right level {})",
+                difficulty.colorize(confusion.name.as_str()),
+                "->".red(),
+                difficulty.colorize(confusion.guess.as_str()),
+                confusion.distance,
+                match confusion.rank {
+                    Some(rank) => format!("ranked #{rank}"),
+                    None => "unknown".to_string(),
+                }
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Where the game's events come from: the Discord gateway, or a recorded log
+//! played back with local screenshots.
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serenity::async_trait;
+use tokio::sync::mpsc;
+
+use crate::level::LevelDifficulty;
+
+/// Where recorded rounds keep their screenshots, named by `image_filename`.
+pub const ROUND_IMAGES: &str = "levels/rounds";
+
+/// Something that happened in a channel we play in.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    /// Sparky posted a screenshot to guess
+    RoundStarted {
+        channel_id: u64,
+        image_url: String,
+        /// `None` if the embed didn't say
+        #[serde(with = "difficulty_name", default)]
+        difficulty: Option<LevelDifficulty>,
+        /// a local copy of the screenshot to use instead of downloading it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image_file: Option<PathBuf>,
+    },
+    /// someone guessed
+    Guess {
+        channel_id: u64,
+        user_id: u64,
+        content: String,
+    },
+    /// Sparky announced who guessed right
+    RoundWon { channel_id: u64, user_id: u64 },
+    /// Sparky announced nobody guessed right
+    RoundTimedOut { channel_id: u64 },
+}
+
+/// Difficulties by their directory name, as people write them.
+mod difficulty_name {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        difficulty: &Option<LevelDifficulty>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        difficulty
+            .map(|difficulty| difficulty.directory())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<LevelDifficulty>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| {
+                name.parse()
+                    .map_err(|_| serde::de::Error::custom(format!("unknown difficulty {name}")))
+            })
+            .transpose()
+    }
+}
+
+/// The name a round's screenshot is kept under in `ROUND_IMAGES`, from its
+/// URL, since Discord names most of them `image.png`.
+pub fn image_filename(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("png");
+    format!("{:08x}.{}", crc32fast::hash(url.as_bytes()), extension)
+}
+
+#[async_trait]
+pub trait GameEventSource: Send {
+    /// The next event, or `None` once there won't be any more.
+    async fn next_event(&mut self) -> Option<GameEvent>;
+
+    /// Whether events can be handled at the same time, rather than strictly
+    /// one after another.
+    fn concurrent(&self) -> bool {
+        false
+    }
+}
+
+/// Events from the Discord gateway, as turned into events by `Handler`.
+pub struct SerenityEvents {
+    rx: mpsc::UnboundedReceiver<GameEvent>,
+    /// where every event is appended, to be played back later
+    log: Option<std::fs::File>,
+}
+
+impl SerenityEvents {
+    /// Returns the source, and the sender to give `Handler`.
+    pub fn new(log: Option<&str>) -> anyhow::Result<(Self, mpsc::UnboundedSender<GameEvent>)> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let log = log
+            .map(|log| {
+                std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log)
+            })
+            .transpose()?;
+        Ok((Self { rx, log }, tx))
+    }
+}
+
+#[async_trait]
+impl GameEventSource for SerenityEvents {
+    async fn next_event(&mut self) -> Option<GameEvent> {
+        let event = self.rx.recv().await?;
+        if let Some(log) = &mut self.log {
+            let mut line = serde_json::to_vec(&event).unwrap();
+            line.push(b'\n');
+            log.write_all(&line).expect("appended to the event log");
+        }
+        Some(event)
+    }
+
+    // serenity hands every message to its own task, so a slow download in
+    // one channel doesn't hold up the others
+    fn concurrent(&self) -> bool {
+        true
+    }
+}
+
+/// Events read back from a JSONL log, with each round's screenshot read from
+/// a folder instead of downloaded.
+pub struct ReplayEvents {
+    events: std::vec::IntoIter<GameEvent>,
+    images: PathBuf,
+}
+
+impl ReplayEvents {
+    /// Reads the whole log. Screenshots are looked up in `images` by the
+    /// event's `image_file`, or by `image_filename` of its URL.
+    pub fn open(log: &str, images: &str) -> anyhow::Result<Self> {
+        let events = std::fs::read_to_string(log)?
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|err| anyhow::anyhow!("{}:{}: {}", log, i + 1, err))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            events: events.into_iter(),
+            images: PathBuf::from(images),
+        })
+    }
+
+    /// How many events are left.
+    pub fn remaining(&self) -> usize {
+        self.events.len()
+    }
+}
+
+#[async_trait]
+impl GameEventSource for ReplayEvents {
+    async fn next_event(&mut self) -> Option<GameEvent> {
+        Some(match self.events.next()? {
+            GameEvent::RoundStarted {
+                channel_id,
+                image_url,
+                difficulty,
+                image_file,
+            } => GameEvent::RoundStarted {
+                channel_id,
+                difficulty,
+                image_file: Some(
+                    self.images
+                        .join(image_file.unwrap_or_else(|| image_filename(&image_url).into())),
+                ),
+                image_url,
+            },
+            event => event,
+        })
+    }
+}
@@ -0,0 +1,949 @@
+//! The guessing game itself: keeping track of each channel's round, guessing
+//! from the screenshot and learning from the answer, whatever the events come
+//! from.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future::Future,
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use bytes::Bytes;
+use colored::{ColoredString, Colorize};
+use serenity::all::{ChannelId, UserId};
+use tokio::{
+    sync::{mpsc, RwLock},
+    task::JoinHandle,
+};
+
+use crate::{
+    confidence::{self, Confidence},
+    download::{check_image, Downloader},
+    events::{image_filename, GameEvent, GameEventSource, ROUND_IMAGES},
+    handler::MENTION_REGEX,
+    journal::{Journal, JournalEntry},
+    level::{
+        normalize_name, unix_time, Coefficients, Level, LevelDifficulty, LevelSet, MAX_SAMPLES,
+    },
+    round::{IllegalStep, Phase, Round, RoundId, Step},
+    search::{self, DifficultyMatch, Guess, SearchResult},
+    storage::{Game, Outcome, Storage},
+    web::WebMessage,
+    CHANNELS, CONFIG,
+};
+
+/// Known guesses at least this confident are highlighted in the console.
+const HIGH_CONFIDENCE: f32 = 0.5;
+
+/// How many samples a difficulty learns before its threshold is learned again.
+const RELEARN_THRESHOLD_AFTER: usize = 20;
+
+/// The latest round in a channel. Finished rounds are kept until the next one
+/// starts, so late updates to their embed aren't taken for a new round.
+#[derive(Debug, Clone)]
+pub struct ChannelState {
+    pub round: Round,
+    pub url: String,
+    /// from when the round is fingerprinted until it's over
+    pub bytes: Option<Bytes>,
+    /// our closest guesses, closest first
+    pub top_guesses: Vec<Guess>,
+    pub confidence: Option<Confidence>,
+    /// from when the round is fingerprinted until it's over
+    pub coefficients: Option<Coefficients>,
+    pub difficulty: LevelDifficulty,
+    /// false if the embed didn't say, and `difficulty` is just where the
+    /// closest match was
+    pub difficulty_known: bool,
+    pub guesses: HashMap<UserId, String>,
+    /// when the round started, in seconds since the Unix epoch
+    pub started_at: u64,
+}
+
+impl ChannelState {
+    fn new(url: String, difficulty: LevelDifficulty, difficulty_known: bool) -> Self {
+        Self {
+            round: Round::start(),
+            url,
+            bytes: None,
+            top_guesses: vec![],
+            confidence: None,
+            coefficients: None,
+            difficulty,
+            difficulty_known,
+            guesses: HashMap::new(),
+            started_at: unix_time(),
+        }
+    }
+
+    /// Keeps the screenshot and its fingerprint for the difficulty the round
+    /// goes with.
+    fn fingerprinted(
+        &mut self,
+        bytes: Bytes,
+        difficulty: LevelDifficulty,
+        coefficients: Coefficients,
+    ) -> Result<(), IllegalStep> {
+        self.round.advance(Step::Fingerprint)?;
+        self.bytes = Some(bytes);
+        self.difficulty = difficulty;
+        self.coefficients = Some(coefficients);
+        Ok(())
+    }
+
+    fn guessed(&mut self, result: &SearchResult) -> Result<(), IllegalStep> {
+        self.round.advance(Step::Guess)?;
+        self.top_guesses = result.guesses.clone();
+        self.confidence = result.confidence;
+        Ok(())
+    }
+
+    /// Wins, times out or abandons the round, handing back the screenshot and
+    /// fingerprint if it got that far.
+    fn end(&mut self, step: Step) -> Result<(Option<Bytes>, Option<Coefficients>), IllegalStep> {
+        self.round.advance(step)?;
+        Ok((self.bytes.take(), self.coefficients.take()))
+    }
+}
+
+/// A channel's state, if it's still on the given round.
+fn current_round(
+    channels: &mut HashMap<ChannelId, ChannelState>,
+    channel_id: ChannelId,
+    round: RoundId,
+) -> Option<&mut ChannelState> {
+    channels
+        .get_mut(&channel_id)
+        .filter(|state| state.round.id == round)
+}
+
+pub type LevelDatabase = Arc<HashMap<LevelDifficulty, RwLock<LevelSet>>>;
+
+/// What the game has spawned, so it can be waited on before exiting.
+#[derive(Clone, Default)]
+pub struct Tasks(Arc<std::sync::Mutex<Vec<JoinHandle<()>>>>);
+
+impl Tasks {
+    fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut handles = self.0.lock().unwrap();
+        handles.retain(|handle| !handle.is_finished());
+        handles.push(tokio::spawn(task));
+    }
+
+    /// Waits for every task, including ones spawned while waiting.
+    pub async fn wait(&self) {
+        loop {
+            let handles = std::mem::take(&mut *self.0.lock().unwrap());
+            if handles.is_empty() {
+                return;
+            }
+            for handle in handles {
+                handle.await.ok();
+            }
+        }
+    }
+}
+
+/// Everything the game shares between events.
+#[derive(Clone)]
+pub struct GameContext {
+    pub channels: Arc<RwLock<HashMap<ChannelId, ChannelState>>>,
+    pub database: LevelDatabase,
+    pub storage: Arc<dyn Storage>,
+    pub downloader: Downloader,
+    pub web: mpsc::UnboundedSender<WebMessage>,
+    /// where what happens in each round is written, if anywhere
+    pub journal: Option<Arc<Journal>>,
+    /// whether screenshots are saved under `levels/`, which simulations only
+    /// do when they learn
+    pub save_images: bool,
+    pub tasks: Tasks,
+}
+
+impl GameContext {
+    fn send_web_message(&self, message: WebMessage) {
+        self.web.send(message).unwrap();
+    }
+
+    fn journal(&self, entry: JournalEntry) {
+        if let Some(journal) = &self.journal {
+            journal.record(&entry);
+        }
+    }
+
+    /// Journals how a round that just ended ended.
+    fn journal_end(&self, state: &ChannelState, answer: Option<&str>) {
+        self.journal(JournalEntry::Ended {
+            round: state.round.id,
+            phase: state.round.phase,
+            answer: answer.map(str::to_owned),
+            at: unix_time(),
+        });
+    }
+
+    /// Adds a finished round to the history of games, warning if it can't.
+    async fn record_game(
+        &self,
+        channel_id: ChannelId,
+        state: &ChannelState,
+        answer: Option<String>,
+        outcome: Outcome,
+    ) {
+        let best = state.top_guesses.first();
+        let game = Game {
+            channel_id: channel_id.to_string(),
+            difficulty: state.difficulty.directory().to_owned(),
+            guess: best.map(|guess| guess.name.to_owned()),
+            distance: best.map(|guess| guess.distance),
+            answer,
+            outcome,
+            started_at: state.started_at,
+            ended_at: unix_time(),
+        };
+
+        if let Err(err) = self.storage.record_game(&game).await {
+            println!(
+                "{} failed to record the game: {:#}",
+                "warning!".yellow().bold(),
+                err
+            );
+        }
+    }
+}
+
+/// Learns a difficulty's threshold again from a copy of its levels, on a
+/// blocking thread, since it matches every sample against every level and
+/// guesses shouldn't wait for it.
+async fn relearn_threshold(database: LevelDatabase, difficulty: LevelDifficulty) {
+    let levels = database.get(&difficulty).unwrap().read().await.snapshot();
+    let threshold = tokio::task::spawn_blocking(move || {
+        confidence::learn_threshold(&levels, CONFIG.sample_match)
+    })
+    .await
+    .unwrap();
+
+    database.get(&difficulty).unwrap().write().await.threshold = threshold;
+}
+
+/// Plays every event from a source until it runs out.
+pub async fn run(game: GameContext, mut source: impl GameEventSource) {
+    while let Some(event) = source.next_event().await {
+        if source.concurrent() {
+            let game = game.clone();
+            let tasks = game.tasks.clone();
+            tasks.spawn(async move { handle_event(&game, event).await });
+        } else {
+            handle_event(&game, event).await;
+        }
+    }
+}
+
+/// How a channel is labelled in the console: its place in the configured
+/// channels, or its ID if it isn't one of them.
+fn channel_prefix(channel_id: ChannelId) -> ColoredString {
+    match CHANNELS.iter().position(|c| c == &channel_id) {
+        Some(i) => format!("[ #{} ]", i + 1),
+        None => format!("[ {} ]", channel_id),
+    }
+    .white()
+}
+
+/// Fingerprints a screenshot for each of `difficulties` and searches their
+/// databases. Databases with the same fingerprint share the computation.
+async fn search_difficulties(
+    database: &LevelDatabase,
+    bytes: &[u8],
+    difficulties: &[LevelDifficulty],
+) -> anyhow::Result<Vec<(LevelDifficulty, Coefficients, SearchResult)>> {
+    let mut computed: HashMap<_, Coefficients> = HashMap::new();
+    let mut results = vec![];
+    for &difficulty in difficulties {
+        let levels = database.get(&difficulty).unwrap().read().await;
+        let coefficients = match computed.entry(levels.fingerprint) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => entry.insert(levels.fingerprint.compute(bytes)?).clone(),
+        };
+
+        let result = search::search(&levels, &coefficients, CONFIG.top_k, CONFIG.sample_match);
+        results.push((difficulty, coefficients, result));
+    }
+    Ok(results)
+}
+
+/// Reads a round's screenshot from its local copy, or downloads it.
+async fn load_image(
+    downloader: &Downloader,
+    url: &str,
+    file: Option<&Path>,
+) -> anyhow::Result<Bytes> {
+    Ok(match file {
+        Some(file) => {
+            let bytes = Bytes::from(tokio::fs::read(file).await?);
+            check_image(&bytes)?;
+            bytes
+        }
+        None => downloader.download(url).await?,
+    })
+}
+
+/// Fails a round whose screenshot we couldn't use, if it's still going.
+async fn round_failed(game: &GameContext, channel_id: ChannelId, round: RoundId, reason: String) {
+    println!(
+        "{} {} {}",
+        channel_prefix(channel_id),
+        "error!".red().bold(),
+        reason
+    );
+
+    let mut channels = game.channels.write().await;
+    let Some(current) = current_round(&mut channels, channel_id, round) else {
+        return;
+    };
+    if current.end(Step::Fail).is_ok() {
+        game.journal_end(current, None);
+        game.send_web_message(WebMessage::GuessFailed {
+            channel_id: channel_id.to_string(),
+            reason,
+        });
+    }
+}
+
+pub async fn handle_event(game: &GameContext, event: GameEvent) {
+    match event {
+        GameEvent::RoundStarted {
+            channel_id,
+            image_url,
+            difficulty,
+            image_file,
+        } => {
+            round_started(
+                game,
+                ChannelId::new(channel_id),
+                image_url,
+                difficulty,
+                image_file.as_deref(),
+            )
+            .await
+        }
+
+        GameEvent::Guess {
+            channel_id,
+            user_id,
+            content,
+        } => {
+            // remove mentions, normalise guesses; guesses between rounds
+            // don't count
+            if let Some(state) = game
+                .channels
+                .write()
+                .await
+                .get_mut(&ChannelId::new(channel_id))
+                .filter(|state| !state.round.is_over())
+            {
+                let guess = normalize_name(&MENTION_REGEX.replace_all(&content, ""));
+                game.journal(JournalEntry::Guess {
+                    round: state.round.id,
+                    user_id,
+                    guess: guess.to_owned(),
+                    at: unix_time(),
+                });
+                state.guesses.insert(UserId::new(user_id), guess);
+            }
+        }
+
+        GameEvent::RoundWon {
+            channel_id,
+            user_id,
+        } => round_won(game, ChannelId::new(channel_id), UserId::new(user_id)).await,
+
+        GameEvent::RoundTimedOut { channel_id } => {
+            let channel_id = ChannelId::new(channel_id);
+
+            let channel_state = {
+                let mut channels = game.channels.write().await;
+                let Some(channel_state) = channels.get_mut(&channel_id) else {
+                    return;
+                };
+                if let Err(err) = channel_state.end(Step::TimeOut) {
+                    println!(
+                        "{} {} ignoring a timeout for {}: {}",
+                        channel_prefix(channel_id),
+                        "warning!".yellow().bold(),
+                        channel_state.round,
+                        err
+                    );
+                    return;
+                }
+                game.journal_end(channel_state, None);
+                channel_state.clone()
+            };
+
+            // only once the round is really over, so a failed round stays
+            // failed in the web UI
+            game.send_web_message(WebMessage::GuessTimeout {
+                channel_id: channel_id.to_string(),
+            });
+
+            game.record_game(channel_id, &channel_state, None, Outcome::TimedOut)
+                .await;
+        }
+    }
+}
+
+async fn round_started(
+    game: &GameContext,
+    channel_id: ChannelId,
+    image_url: String,
+    stated: Option<LevelDifficulty>,
+    image_file: Option<&Path>,
+) {
+    let channel_prefix = channel_prefix(channel_id);
+    let state = &game.channels;
+
+    // without a difficulty, every database is searched and the round goes
+    // with whichever has the most convincing match
+    let difficulty = stated.unwrap_or(LevelDifficulty::Easy);
+
+    // immediately set base channel state
+    let round = {
+        let mut channels = state.write().await;
+        if let Some(current) = channels.get_mut(&channel_id) {
+            if current.url == image_url {
+                // this URL is already the round here, unimportant message
+                // update
+                return;
+            }
+
+            if current.end(Step::Abandon).is_ok() {
+                println!(
+                    "{} abandoning {}, the next one started before it ended",
+                    channel_prefix, current.round
+                );
+                game.journal_end(current, None);
+            }
+        }
+
+        let new = ChannelState::new(image_url.to_owned(), difficulty, stated.is_some());
+        let round = new.round.clone();
+        channels.insert(channel_id, new);
+        round
+    };
+    match stated {
+        Some(difficulty) => println!("{} new {} level ({})", channel_prefix, difficulty, round),
+        None => println!(
+            "{} new level of unknown difficulty, searching every difficulty ({})",
+            channel_prefix, round
+        ),
+    }
+    let round = round.id;
+    game.journal(JournalEntry::Started {
+        round,
+        channel_id: channel_id.get(),
+        image_url: image_url.to_owned(),
+        difficulty: stated.map(|difficulty| difficulty.directory().to_owned()),
+        at: unix_time(),
+    });
+
+    // send web message
+    game.send_web_message(WebMessage::GuessStart {
+        channel_id: channel_id.to_string(),
+        difficulty,
+        difficulty_known: stated.is_some(),
+    });
+
+    // the round can already be over if Sparky was quick about it
+    let Some(Ok(_)) = current_round(&mut *state.write().await, channel_id, round)
+        .map(|current| current.round.advance(Step::Download))
+    else {
+        return;
+    };
+
+    let downloading = Instant::now();
+    let bytes = match load_image(&game.downloader, &image_url, image_file).await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            let reason = format!("could not load the screenshot: {:#}", err);
+            round_failed(game, channel_id, round, reason).await;
+            return;
+        }
+    };
+    game.journal(JournalEntry::Downloaded {
+        round,
+        image_crc32: format!("{:08x}", crc32fast::hash(&bytes)),
+        size: bytes.len(),
+        millis: downloading.elapsed().as_millis() as u64,
+    });
+
+    // keep recorded rounds' screenshots so the log can be played back
+    if CONFIG.event_log.is_some() && image_file.is_none() {
+        let path = Path::new(ROUND_IMAGES).join(image_filename(&image_url));
+        let bytes = bytes.clone();
+        game.tasks.spawn(async move {
+            tokio::fs::create_dir_all(ROUND_IMAGES)
+                .await
+                .expect("failed to create the round images folder");
+            tokio::fs::write(path, bytes)
+                .await
+                .expect("failed to save round image")
+        });
+    }
+
+    let level_state = &game.database;
+
+    // get our best guesses
+    let difficulties = match stated {
+        Some(difficulty) if !CONFIG.search_all_difficulties => vec![difficulty],
+        _ => LevelDifficulty::ALL.to_vec(),
+    };
+    let searching = Instant::now();
+    let mut results = match search_difficulties(level_state, &bytes, &difficulties).await {
+        Ok(results) => results,
+        Err(err) => {
+            let reason = format!("could not read the screenshot: {:#}", err);
+            round_failed(game, channel_id, round, reason).await;
+            return;
+        }
+    };
+
+    let primary = match stated {
+        Some(difficulty) => results.iter().position(|(d, ..)| *d == difficulty),
+        None => (0..results.len()).reduce(|best, i| {
+            if results[i].2.beats(&results[best].2) {
+                i
+            } else {
+                best
+            }
+        }),
+    };
+    let (difficulty, coefficients, result) = results.swap_remove(primary.unwrap());
+
+    // keep the fingerprint, unless the round ended while we worked on it
+    let fingerprinted = JournalEntry::Fingerprinted {
+        round,
+        difficulty: difficulty.directory().to_owned(),
+        fingerprint: level_state
+            .get(&difficulty)
+            .unwrap()
+            .read()
+            .await
+            .fingerprint,
+        coefficients: coefficients.0.clone(),
+    };
+    let Some(Ok(())) = current_round(&mut *state.write().await, channel_id, round)
+        .map(|current| current.fingerprinted(bytes.to_owned(), difficulty, coefficients))
+    else {
+        return;
+    };
+    game.journal(fingerprinted);
+
+    // a closer match somewhere else means the level is probably filed under
+    // the wrong difficulty, or Sparky got it wrong
+    let elsewhere = results
+        .into_iter()
+        .filter(|(_, _, other)| other.beats(&result))
+        .reduce(|best, other| if other.2.beats(&best.2) { other } else { best })
+        .and_then(|(difficulty, _, other)| {
+            Some(DifficultyMatch {
+                difficulty,
+                guess: other.best()?.clone(),
+                confidence: other.confidence?,
+            })
+        });
+
+    // keep our guesses, unless the round ended in the meantime
+    let Some(Ok(())) = current_round(&mut *state.write().await, channel_id, round)
+        .map(|current| current.guessed(&result))
+    else {
+        return;
+    };
+    game.journal(JournalEntry::Guessed {
+        round,
+        guesses: result.guesses.clone(),
+        confidence: result.confidence.map(|confidence| confidence.score),
+        millis: searching.elapsed().as_millis() as u64,
+    });
+
+    if stated.is_none() {
+        println!(
+            "{} going with {}, which has the closest match",
+            channel_prefix, difficulty
+        );
+    }
+
+    if let (Some(best_guess), Some(confidence)) = (result.best(), result.confidence) {
+        if confidence.known {
+            println!(
+                "{} my best guess is {} (dist {}, {:.0}% confident{})",
+                channel_prefix,
+                difficulty.colorize(best_guess.name.as_str()),
+                best_guess.distance,
+                confidence.score * 100f32,
+                if confidence.score >= HIGH_CONFIDENCE {
+                    " !!!".bold().bright_yellow().to_string()
+                } else {
+                    "".to_string()
+                }
+            );
+        } else {
+            println!(
+                "{} probably a level I've never seen, closest is {} (dist {})",
+                channel_prefix,
+                difficulty.colorize(best_guess.name.as_str()),
+                best_guess.distance,
+            );
+        }
+
+        if let [_, runners_up @ ..] = result.guesses.as_slice() {
+            if !runners_up.is_empty() {
+                println!(
+                    "{} runners-up: {}",
+                    channel_prefix,
+                    runners_up
+                        .iter()
+                        .map(|guess| format!(
+                            "{} ({})",
+                            difficulty.colorize(guess.name.as_str()),
+                            guess.distance
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+        }
+    }
+
+    if let Some(other) = &elsewhere {
+        println!(
+            "{} {} has a better match: {} (dist {}, {:.0}% confident)",
+            channel_prefix,
+            other.difficulty,
+            other.difficulty.colorize(other.guess.name.as_str()),
+            other.guess.distance,
+            other.confidence.score * 100f32
+        );
+    }
+
+    // save active image
+    if game.save_images {
+        let filename = format!("levels/{}.png", channel_id);
+
+        game.tasks.spawn(async move {
+            let path = Path::new(&filename);
+            tokio::fs::write(path, bytes)
+                .await
+                .expect("failed to save image")
+        });
+    }
+
+    // send web message with guess
+    game.send_web_message(WebMessage::GuessData {
+        channel_id: channel_id.to_string(),
+        difficulty,
+        guesses: result.guesses,
+        confidence: result.confidence,
+        elsewhere,
+    });
+}
+
+async fn round_won(game: &GameContext, channel_id: ChannelId, winner: UserId) {
+    let channel_prefix = channel_prefix(channel_id);
+
+    // ignore if we didn't have a round in this channel
+    let (mut channel_state, phase, bytes, mut coefficients) = {
+        let mut channels = game.channels.write().await;
+        let Some(channel_state) = channels.get_mut(&channel_id) else {
+            return;
+        };
+        let phase = channel_state.round.phase;
+        match channel_state.end(Step::Win) {
+            Ok((bytes, coefficients)) => (channel_state.clone(), phase, bytes, coefficients),
+            Err(err) => {
+                println!(
+                    "{} {} ignoring a win for {}: {}",
+                    channel_prefix,
+                    "warning!".yellow().bold(),
+                    channel_state.round,
+                    err
+                );
+                return;
+            }
+        }
+    };
+
+    let answer = channel_state.guesses.get(&winner).cloned();
+    let Some(answer) = answer else {
+        game.journal_end(&channel_state, None);
+        println!(
+            "{} {} the winner of {} didn't guess anything we saw",
+            channel_prefix,
+            "warning!".yellow().bold(),
+            channel_state.round
+        );
+        return;
+    };
+    let level_state = &game.database;
+
+    // without a stated difficulty, a level can only be filed where we already
+    // have it
+    if !channel_state.difficulty_known {
+        let mut found = None;
+        for difficulty in LevelDifficulty::ALL {
+            let levels = level_state.get(&difficulty).unwrap().read().await;
+            if levels.resolve(&answer).is_some() {
+                found = Some((difficulty, levels.fingerprint));
+                break;
+            }
+        }
+
+        match found {
+            Some((difficulty, fingerprint)) => {
+                if difficulty != channel_state.difficulty {
+                    coefficients = match bytes
+                        .as_ref()
+                        .map(|bytes| fingerprint.compute(bytes))
+                        .transpose()
+                    {
+                        Ok(coefficients) => coefficients,
+                        Err(err) => {
+                            // the round can't be learned from, so it failed
+                            // rather than was won
+                            let reason = format!(
+                                "could not fingerprint the screenshot of {} for {}: {:#}",
+                                channel_state.round, difficulty, err
+                            );
+                            println!(
+                                "{} {} {}, not learning from it",
+                                channel_prefix,
+                                "warning!".yellow().bold(),
+                                reason
+                            );
+
+                            channel_state.round.phase = Phase::Failed;
+                            let mut channels = game.channels.write().await;
+                            if let Some(current) =
+                                current_round(&mut channels, channel_id, channel_state.round.id)
+                            {
+                                current.round.phase = Phase::Failed;
+                            }
+                            drop(channels);
+
+                            game.journal_end(&channel_state, Some(&answer));
+                            game.send_web_message(WebMessage::GuessFailed {
+                                channel_id: channel_id.to_string(),
+                                reason,
+                            });
+                            game.record_game(
+                                channel_id,
+                                &channel_state,
+                                Some(answer),
+                                Outcome::Incorrect,
+                            )
+                            .await;
+
+                            return;
+                        }
+                    };
+                    channel_state.difficulty = difficulty;
+                }
+            }
+            None => {
+                game.journal_end(&channel_state, Some(&answer));
+                println!(
+                    "{} {} {} is new but its difficulty is unknown, not saving it",
+                    channel_prefix,
+                    "warning!".yellow().bold(),
+                    answer.bold()
+                );
+
+                game.send_web_message(WebMessage::GuessWin {
+                    channel_id: channel_id.to_string(),
+                    answer: answer.to_owned(),
+                    incorrect: false,
+                });
+
+                game.record_game(channel_id, &channel_state, Some(answer), Outcome::Incorrect)
+                    .await;
+
+                return;
+            }
+        }
+    }
+
+    game.journal_end(&channel_state, Some(&answer));
+
+    // file the answer under the level it names, however it was spelled
+    let answer = {
+        let levels = level_state
+            .get(&channel_state.difficulty)
+            .unwrap()
+            .read()
+            .await;
+        levels.resolve(&answer).unwrap_or(&answer).to_owned()
+    };
+
+    // save the image in another thread if we don't already have it
+    if let Some(bytes) = bytes.filter(|_| game.save_images) {
+        let filename = channel_state.difficulty.image_path(&answer);
+
+        game.tasks.spawn(async move {
+            // TODO: save when we update coefficients
+            let path = Path::new(&filename);
+            if !path.exists() {
+                tokio::fs::write(path, bytes)
+                    .await
+                    .expect("failed to save image")
+            }
+        });
+    }
+
+    // check if our guess was correct
+    match channel_state.top_guesses.first() {
+        Some(my_guess) if my_guess.name == answer => {
+            println!(
+                "{} {} my guess was correct: {} (dist {})",
+                channel_prefix,
+                "I was right!".bold().underline(),
+                channel_state
+                    .difficulty
+                    .colorize(my_guess.name.as_str())
+                    .bold(),
+                my_guess.distance,
+            );
+
+            game.send_web_message(WebMessage::GuessWin {
+                channel_id: channel_id.to_string(),
+                answer: my_guess.name.to_string(),
+                incorrect: false,
+            });
+
+            let mut levels = level_state
+                .get(&channel_state.difficulty)
+                .unwrap()
+                .write()
+                .await;
+            if let Some(level) = levels.get_mut(&answer) {
+                level.metadata.record_round(unix_time(), true);
+                game.storage
+                    .save_level(channel_state.difficulty, &levels, &answer)
+                    .await
+                    .expect("saved levels");
+            }
+
+            game.record_game(channel_id, &channel_state, Some(answer), Outcome::Correct)
+                .await;
+
+            return;
+        }
+        _ => (),
+    }
+
+    println!(
+        "{} I was wrong, winning guess: {}{}",
+        channel_prefix,
+        channel_state.difficulty.colorize(answer.as_str().bold()),
+        match channel_state
+            .top_guesses
+            .iter()
+            .position(|guess| guess.name == answer)
+        {
+            Some(i) => format!(" (my #{} guess)", i + 1),
+            None => "".to_string(),
+        }
+    );
+
+    // Sparky can end a round before we've even seen its screenshot
+    let Some(coefficients) = coefficients else {
+        println!(
+            "{} {} {} was won while {}, not learning from it",
+            channel_prefix,
+            "warning!".yellow().bold(),
+            channel_state.round,
+            phase
+        );
+
+        game.send_web_message(WebMessage::GuessWin {
+            channel_id: channel_id.to_string(),
+            answer: answer.to_owned(),
+            incorrect: false,
+        });
+
+        game.record_game(channel_id, &channel_state, Some(answer), Outcome::Incorrect)
+            .await;
+
+        return;
+    };
+
+    // if we already knew the winning level, this screenshot didn't look
+    // enough like the ones we have, so keep it as another sample
+    let incorrect = {
+        let mut levels = level_state
+            .get(&channel_state.difficulty)
+            .unwrap()
+            .write()
+            .await;
+
+        let incorrect = match levels.get_mut(&answer) {
+            Some(level) => {
+                level.add_sample(coefficients);
+                println!(
+                    "{} {} added sample {}/{}",
+                    channel_prefix,
+                    "I already knew that one!".red(),
+                    level.samples.len(),
+                    MAX_SAMPLES
+                );
+                true
+            }
+            None => {
+                levels.insert(
+                    answer.to_owned(),
+                    Level::new(answer.to_owned(), channel_state.difficulty, coefficients),
+                );
+                false
+            }
+        };
+
+        levels
+            .get_mut(&answer)
+            .unwrap()
+            .metadata
+            .record_round(unix_time(), false);
+        levels.reindex(&answer);
+
+        levels.unthresholded += 1;
+        if levels.unthresholded >= RELEARN_THRESHOLD_AFTER {
+            levels.unthresholded = 0;
+            game.tasks.spawn(relearn_threshold(
+                Arc::clone(level_state),
+                channel_state.difficulty,
+            ));
+        }
+        incorrect
+    };
+
+    // notify web of result
+    game.send_web_message(WebMessage::GuessWin {
+        channel_id: channel_id.to_string(),
+        answer: answer.to_owned(),
+        incorrect,
+    });
+
+    // save the level we learned
+    {
+        let levels = level_state
+            .get(&channel_state.difficulty)
+            .unwrap()
+            .read()
+            .await;
+
+        game.storage
+            .save_level(channel_state.difficulty, &levels, &answer)
+            .await
+            .expect("saved levels");
+    }
+
+    game.record_game(channel_id, &channel_state, Some(answer), Outcome::Incorrect)
+        .await;
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+
+use crate::level::{Coefficients, Level, LevelSet, Measure, SampleMatch};
+
+/// The fewest points matched linearly before the tree is rebuilt.
+const MIN_PENDING: usize = 256;
+
+/// A fingerprint the index can return, along with the level it belongs to.
+struct Point {
+    level: String,
+    coefficients: Coefficients,
+}
+
+/// A vantage point. Points no farther from it than `radius` are under `inside`,
+/// the rest under `outside`.
+struct Node {
+    point: usize,
+    radius: f32,
+    inside: Option<usize>,
+    outside: Option<usize>,
+}
+
+/// The `k` closest distinct levels found so far, closest first.
+struct Nearest {
+    k: usize,
+    found: Vec<(String, f32)>,
+}
+
+impl Nearest {
+    /// Keeps the `k` closest of at most `levels` levels.
+    fn new(k: usize, levels: usize) -> Self {
+        Self {
+            k,
+            found: Vec::with_capacity(k.min(levels) + 1),
+        }
+    }
+
+    /// The distance a point has to beat to be kept.
+    fn bound(&self) -> f32 {
+        if self.found.len() < self.k {
+            f32::INFINITY
+        } else {
+            self.found[self.k - 1].1
+        }
+    }
+
+    fn offer(&mut self, level: &str, distance: f32) {
+        if distance >= self.bound() {
+            return;
+        }
+
+        // a level only counts once, at its closest sample
+        if let Some(i) = self.found.iter().position(|(name, _)| name == level) {
+            if self.found[i].1 <= distance {
+                return;
+            }
+            self.found.remove(i);
+        }
+
+        let i = self.found.partition_point(|(_, d)| *d <= distance);
+        self.found.insert(i, (level.to_owned(), distance));
+        self.found.truncate(self.k);
+    }
+}
+
+/// A vantage-point tree over the fingerprints of a `LevelSet`, so a guess only
+/// has to be compared against a fraction of the levels.
+///
+/// Search is exact, but relies on the fingerprint's distance being a metric.
+/// Levels that change after the tree is built are marked stale and matched
+/// linearly until there are enough of them to be worth a rebuild.
+pub struct LevelIndex {
+    measure: Measure,
+    mode: SampleMatch,
+    points: Vec<Point>,
+    nodes: Vec<Node>,
+    root: Option<usize>,
+    /// levels whose points in the tree are out of date
+    stale: HashSet<String>,
+    /// points added since the tree was built
+    pending: Vec<Point>,
+}
+
+/// The points a level is matched by.
+fn level_points(level: &Level, mode: SampleMatch) -> Vec<Point> {
+    let point = |coefficients: &Coefficients| Point {
+        level: level.name.to_owned(),
+        coefficients: coefficients.clone(),
+    };
+
+    match mode {
+        SampleMatch::Nearest => level.samples.iter().map(point).collect(),
+        SampleMatch::Centroid => vec![point(&level.centroid)],
+    }
+}
+
+impl LevelIndex {
+    /// Builds an index over every level. `None` if the fingerprint's distance
+    /// isn't a metric, in which case searches have to be exact.
+    pub fn build(levels: &LevelSet, mode: SampleMatch) -> Option<Self> {
+        if !levels.fingerprint.is_metric() {
+            return None;
+        }
+
+        let points = levels
+            .values()
+            .flat_map(|level| level_points(level, mode))
+            .collect::<Vec<_>>();
+
+        let mut index = Self {
+            measure: levels.measure(),
+            mode,
+            nodes: Vec::with_capacity(points.len()),
+            points,
+            root: None,
+            stale: HashSet::new(),
+            pending: vec![],
+        };
+
+        let mut order = (0..index.points.len()).collect::<Vec<_>>();
+        index.root = index.build_node(&mut order);
+        Some(index)
+    }
+
+    fn build_node(&mut self, points: &mut [usize]) -> Option<usize> {
+        let (&mut vantage, rest) = points.split_first_mut()?;
+
+        let mut radius = 0f32;
+        let mut split = 0;
+        if !rest.is_empty() {
+            let vantage = &self.points[vantage].coefficients;
+            let mut distances = rest
+                .iter()
+                .map(|&i| {
+                    (
+                        self.measure.distance(vantage, &self.points[i].coefficients),
+                        i,
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            split = distances.len() / 2;
+            distances.select_nth_unstable_by(split, |(a, _), (b, _)| a.total_cmp(b));
+            radius = distances[split].0;
+
+            for (slot, (_, i)) in rest.iter_mut().zip(distances) {
+                *slot = i;
+            }
+        }
+
+        let (inside, outside) = rest.split_at_mut(split);
+        let inside = self.build_node(inside);
+        let outside = self.build_node(outside);
+
+        self.nodes.push(Node {
+            point: vantage,
+            radius,
+            inside,
+            outside,
+        });
+        Some(self.nodes.len() - 1)
+    }
+
+    pub fn mode(&self) -> SampleMatch {
+        self.mode
+    }
+
+    /// Brings a level's points up to date after it was added, changed or
+    /// (with `None`) removed.
+    pub fn update(&mut self, name: &str, level: Option<&Level>) {
+        self.stale.insert(name.to_owned());
+        self.pending.retain(|point| point.level != name);
+
+        if let Some(level) = level {
+            self.pending.extend(level_points(level, self.mode));
+        }
+    }
+
+    /// Whether enough has changed since the tree was built that searches would
+    /// be quicker after a rebuild.
+    pub fn needs_rebuild(&self) -> bool {
+        self.pending.len() > MIN_PENDING.max(self.points.len() / 8)
+    }
+
+    /// Finds the `k` closest levels, closest first.
+    pub fn search(&self, coefficients: &Coefficients, k: usize) -> Vec<(String, f32)> {
+        let mut nearest = Nearest::new(k.max(1), self.points.len() + self.pending.len());
+
+        for point in &self.pending {
+            nearest.offer(
+                &point.level,
+                self.measure.distance(&point.coefficients, coefficients),
+            );
+        }
+
+        // walk the tree with a lower bound on the distance to everything under
+        // each node, closer side first, skipping whatever can't beat the
+        // closest levels found by the time it comes up
+        let mut stack = self
+            .root
+            .map(|root| (root, 0f32))
+            .into_iter()
+            .collect::<Vec<_>>();
+        while let Some((node, lower_bound)) = stack.pop() {
+            if lower_bound >= nearest.bound() {
+                continue;
+            }
+
+            let node = &self.nodes[node];
+            let point = &self.points[node.point];
+            let distance = self.measure.distance(&point.coefficients, coefficients);
+
+            if !self.stale.contains(&point.level) {
+                nearest.offer(&point.level, distance);
+            }
+
+            let inside = node
+                .inside
+                .map(|i| (i, (distance - node.radius).max(lower_bound)));
+            let outside = node
+                .outside
+                .map(|i| (i, (node.radius - distance).max(lower_bound)));
+
+            if distance < node.radius {
+                stack.extend(outside);
+                stack.extend(inside);
+            } else {
+                stack.extend(inside);
+                stack.extend(outside);
+            }
+        }
+
+        nearest.found
+    }
+}
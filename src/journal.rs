@@ -0,0 +1,398 @@
+//! A JSONL journal of everything that happens in each round, rotated once it
+//! gets big, so rounds can be looked at and replayed against a newer matcher.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    level::{Coefficients, FingerprintKind, LevelDifficulty, LevelSet, SampleMatch},
+    round::{Phase, RoundId},
+    search::{self, Guess},
+};
+
+/// One thing that happened in a round. Round IDs start over when the bot
+/// restarts, so a `Started` entry begins a new round even if its ID was seen
+/// before.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Started {
+        round: RoundId,
+        channel_id: u64,
+        image_url: String,
+        /// the difficulty's directory name, if the embed said
+        difficulty: Option<String>,
+        /// in seconds since the Unix epoch
+        at: u64,
+    },
+    /// the screenshot was loaded
+    Downloaded {
+        round: RoundId,
+        /// CRC32 of the screenshot, in hex
+        image_crc32: String,
+        size: usize,
+        millis: u64,
+    },
+    /// the screenshot's fingerprint for the difficulty the round went with
+    Fingerprinted {
+        round: RoundId,
+        difficulty: String,
+        fingerprint: FingerprintKind,
+        coefficients: Vec<f32>,
+    },
+    /// our closest guesses, closest first
+    Guessed {
+        round: RoundId,
+        guesses: Vec<Guess>,
+        /// how sure we were of the closest one, from 0 to 1
+        confidence: Option<f32>,
+        /// how long fingerprinting and searching took
+        millis: u64,
+    },
+    /// someone's guess, normalised
+    Guess {
+        round: RoundId,
+        user_id: u64,
+        guess: String,
+        at: u64,
+    },
+    Ended {
+        round: RoundId,
+        /// won, timed out, abandoned or failed
+        phase: Phase,
+        /// the winner's guess, if we saw it
+        answer: Option<String>,
+        at: u64,
+    },
+}
+
+impl JournalEntry {
+    pub fn round(&self) -> RoundId {
+        match self {
+            Self::Started { round, .. }
+            | Self::Downloaded { round, .. }
+            | Self::Fingerprinted { round, .. }
+            | Self::Guessed { round, .. }
+            | Self::Guess { round, .. }
+            | Self::Ended { round, .. } => *round,
+        }
+    }
+}
+
+/// Where the `n`th most recent rotated journal is kept, e.g. `journal.jsonl.1`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+struct JournalFile {
+    file: File,
+    size: u64,
+}
+
+/// The journal being written to.
+pub struct Journal {
+    path: PathBuf,
+    /// the size past which the journal is rotated
+    max_bytes: u64,
+    /// how many rotated journals are kept
+    keep: usize,
+    file: Mutex<JournalFile>,
+}
+
+impl Journal {
+    pub fn open(path: &str, max_bytes: u64, keep: usize) -> anyhow::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path: PathBuf::from(path),
+            max_bytes,
+            keep,
+            file: Mutex::new(JournalFile { file, size }),
+        })
+    }
+
+    /// Appends an entry, warning if it can't.
+    pub fn record(&self, entry: &JournalEntry) {
+        let mut line = serde_json::to_vec(entry).unwrap();
+        line.push(b'\n');
+
+        let mut file = self.file.lock().unwrap();
+        if let Err(err) = self.append(&mut file, &line) {
+            println!(
+                "{} failed to write to the journal: {:#}",
+                "warning!".yellow().bold(),
+                err
+            );
+        }
+    }
+
+    fn append(&self, file: &mut JournalFile, line: &[u8]) -> anyhow::Result<()> {
+        if file.size > 0 && file.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            file.file = File::create(&self.path)?;
+            file.size = 0;
+        }
+
+        file.file.write_all(line)?;
+        file.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Shifts every rotated journal up by one, dropping the oldest, and moves
+    /// the current one to `.1`.
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.keep == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+
+        let oldest = rotated_path(&self.path, self.keep);
+        if oldest.exists() {
+            std::fs::remove_file(oldest)?;
+        }
+        for n in (1..self.keep).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                std::fs::rename(from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+/// Reads a journal along with its rotated files, oldest first.
+pub fn read(path: &str) -> anyhow::Result<Vec<JournalEntry>> {
+    let path = Path::new(path);
+    let mut files = (1..)
+        .map(|n| rotated_path(path, n))
+        .take_while(|rotated| rotated.exists())
+        .collect::<Vec<_>>();
+    files.reverse();
+    if path.exists() || files.is_empty() {
+        files.push(path.to_owned());
+    }
+
+    let mut entries = vec![];
+    for file in files {
+        let contents = std::fs::read_to_string(&file)
+            .map_err(|err| anyhow::anyhow!("{}: {}", file.display(), err))?;
+        for (i, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(
+                serde_json::from_str(line)
+                    .map_err(|err| anyhow::anyhow!("{}:{}: {}", file.display(), i + 1, err))?,
+            );
+        }
+    }
+    Ok(entries)
+}
+
+/// A round put back together from its entries.
+#[derive(Debug, Default)]
+pub struct JournaledRound {
+    pub round: RoundId,
+    /// the stated difficulty, until the round goes with one
+    pub difficulty: Option<String>,
+    pub fingerprint: Option<(FingerprintKind, Coefficients)>,
+    /// what we guessed at the time, closest first
+    pub guesses: Vec<Guess>,
+    /// the winner's guess
+    pub answer: Option<String>,
+}
+
+/// Groups entries into rounds, in the order they started. Entries for rounds
+/// that didn't start in the journal are dropped.
+pub fn rounds(entries: Vec<JournalEntry>) -> Vec<JournaledRound> {
+    let mut rounds: Vec<JournaledRound> = vec![];
+    // where each round ID's latest round is
+    let mut positions: HashMap<RoundId, usize> = HashMap::new();
+
+    for entry in entries {
+        if let JournalEntry::Started {
+            round, difficulty, ..
+        } = entry
+        {
+            positions.insert(round, rounds.len());
+            rounds.push(JournaledRound {
+                round,
+                difficulty,
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let Some(&position) = positions.get(&entry.round()) else {
+            continue;
+        };
+        let journaled = &mut rounds[position];
+        match entry {
+            JournalEntry::Fingerprinted {
+                difficulty,
+                fingerprint,
+                coefficients,
+                ..
+            } => {
+                journaled.difficulty = Some(difficulty);
+                journaled.fingerprint = Some((fingerprint, Coefficients(coefficients)));
+            }
+            JournalEntry::Guessed { guesses, .. } => journaled.guesses = guesses,
+            JournalEntry::Ended { answer, .. } => journaled.answer = answer,
+            _ => (),
+        }
+    }
+
+    rounds
+}
+
+/// What the matcher guesses now for a round, next to what it guessed then.
+#[derive(Debug, Serialize)]
+pub struct Replayed {
+    pub round: RoundId,
+    pub difficulty: LevelDifficulty,
+    /// the level the winner named, as it's filed now
+    pub answer: String,
+    pub then: Option<Guess>,
+    pub now: Option<Guess>,
+    /// whether each guess was the answer
+    pub then_correct: bool,
+    pub now_correct: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ReplayReport {
+    pub rounds: usize,
+    /// rounds nobody won, or that ended before they were fingerprinted
+    pub skipped: usize,
+    /// rounds whose database has since changed fingerprint
+    pub refingerprinted: usize,
+    pub replayed: Vec<Replayed>,
+}
+
+/// Matches every won round's fingerprint against the database as it is now.
+///
+/// The database has learned from the rounds it got wrong, so those will
+/// usually match their own sample now; the interesting changes are in the
+/// rounds it got right.
+pub fn replay(
+    rounds: &[JournaledRound],
+    database: &HashMap<LevelDifficulty, LevelSet>,
+    k: usize,
+    mode: SampleMatch,
+) -> ReplayReport {
+    let mut report = ReplayReport {
+        rounds: rounds.len(),
+        ..Default::default()
+    };
+
+    for journaled in rounds {
+        let (Some(answer), Some((fingerprint, coefficients)), Some(difficulty)) = (
+            &journaled.answer,
+            &journaled.fingerprint,
+            journaled
+                .difficulty
+                .as_ref()
+                .and_then(|difficulty| difficulty.parse::<LevelDifficulty>().ok()),
+        ) else {
+            report.skipped += 1;
+            continue;
+        };
+
+        let levels = &database[&difficulty];
+        if levels.fingerprint != *fingerprint {
+            report.refingerprinted += 1;
+            continue;
+        }
+
+        // names may have been merged or renamed since
+        let filed = |name: &str| levels.resolve(name).unwrap_or(name).to_owned();
+        let answer = filed(answer);
+        let then = journaled.guesses.first().cloned();
+        let now = search::search(levels, coefficients, k, mode)
+            .best()
+            .cloned();
+
+        report.replayed.push(Replayed {
+            round: journaled.round,
+            difficulty,
+            then_correct: then
+                .as_ref()
+                .is_some_and(|then| filed(&then.name) == answer),
+            now_correct: now.as_ref().is_some_and(|now| now.name == answer),
+            answer,
+            then,
+            now,
+        });
+    }
+
+    report
+}
+
+impl ReplayReport {
+    /// Prints the totals and the rounds whose guess changed, or every round
+    /// with `all`.
+    pub fn print(&self, all: bool) {
+        let guess_name = |guess: &Option<Guess>| match guess {
+            Some(guess) => format!("{} (dist {:.2})", guess.name, guess.distance),
+            None => "nothing".to_owned(),
+        };
+
+        for replayed in &self.replayed {
+            let status = match (replayed.then_correct, replayed.now_correct) {
+                (false, true) => "fixed".green().bold(),
+                (true, false) => "broken".red().bold(),
+                _ if !all => continue,
+                (true, true) => "right".normal(),
+                (false, false) => "wrong".normal(),
+            };
+            println!(
+                "  {} round {} ({}): {}, guessed {} then, {} now",
+                status,
+                replayed.round,
+                replayed.difficulty,
+                replayed.difficulty.colorize(replayed.answer.as_str()),
+                guess_name(&replayed.then),
+                guess_name(&replayed.now)
+            );
+        }
+
+        let replayed = self.replayed.len();
+        let percent = |count: usize| {
+            if replayed == 0 {
+                0f32
+            } else {
+                count as f32 / replayed as f32 * 100f32
+            }
+        };
+        let then = self.replayed.iter().filter(|r| r.then_correct).count();
+        let now = self.replayed.iter().filter(|r| r.now_correct).count();
+
+        println!(
+            "replayed {} of {} rounds ({} not won or not fingerprinted, {} fingerprinted differently)",
+            replayed, self.rounds, self.skipped, self.refingerprinted
+        );
+        println!(
+            "  right then {}/{} ({:.1}%), now {}/{} ({})",
+            then,
+            replayed,
+            percent(then),
+            now,
+            replayed,
+            format!("{:.1}%", percent(now)).bold()
+        );
+    }
+}
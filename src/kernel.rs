@@ -0,0 +1,214 @@
+//! A flat scan over every fingerprint in a database, laid out so the compiler
+//! can vectorise it. Kept free of the rest of the crate so it can live in the
+//! library the benchmarks use.
+
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+
+/// How many points are compared at once; the inner loops work on arrays of
+/// this many values, which become SIMD registers.
+const LANES: usize = 8;
+
+/// How many points each thread compares at a time.
+const CHUNK: usize = 64 * LANES;
+
+/// How the kernel compares (already weighted) fingerprints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// the Euclidean distance within each block of `block` values, summed
+    BlockEuclidean { block: usize },
+    /// the sum of absolute differences
+    Manhattan,
+}
+
+/// A candidate for the closest levels, ordered by distance.
+struct Candidate {
+    distance: f32,
+    level: usize,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance.total_cmp(&other.distance)
+    }
+}
+
+pub struct Kernel {
+    metric: Metric,
+    dim: usize,
+    /// what every value is multiplied by before it's stored or compared, so
+    /// the weights cost nothing at search time
+    scales: Vec<f32>,
+    /// what every distance is multiplied by at the end
+    output_scale: f32,
+    /// the number of points, rounded up to a multiple of `LANES`
+    stride: usize,
+    /// `values[i * stride + point]` is the `i`th value of a point
+    values: Vec<f32>,
+    /// the level each point belongs to; a level's points are adjacent
+    owners: Vec<usize>,
+    names: Vec<String>,
+}
+
+impl Kernel {
+    /// Lays out the points of every level. `weights` has one entry per value;
+    /// Euclidean weights apply to squared differences, like they do in the
+    /// fingerprints.
+    pub fn new<'a>(
+        metric: Metric,
+        weights: &[f32],
+        output_scale: f32,
+        levels: impl IntoIterator<Item = (String, Vec<&'a [f32]>)>,
+    ) -> Self {
+        let scales = weights
+            .iter()
+            .map(|weight| match metric {
+                Metric::BlockEuclidean { .. } => weight.sqrt(),
+                Metric::Manhattan => *weight,
+            })
+            .collect::<Vec<_>>();
+
+        let mut names = vec![];
+        let mut owners = vec![];
+        let mut points = vec![];
+        for (name, samples) in levels {
+            for sample in samples {
+                owners.push(names.len());
+                points.push(sample);
+            }
+            names.push(name);
+        }
+
+        let dim = weights.len();
+        let stride = points.len().div_ceil(LANES) * LANES;
+        let mut values = vec![0f32; dim * stride];
+        for (point, sample) in points.iter().enumerate() {
+            for (i, (value, scale)) in sample.iter().zip(&scales).enumerate() {
+                values[i * stride + point] = value * scale;
+            }
+        }
+
+        Self {
+            metric,
+            dim,
+            scales,
+            output_scale,
+            stride,
+            values,
+            owners,
+            names,
+        }
+    }
+
+    /// Distances from the (already scaled) query to `LANES` points starting at
+    /// `start`.
+    fn distances(&self, query: &[f32], start: usize) -> [f32; LANES] {
+        let mut total = [0f32; LANES];
+        let value = |i: usize| -> &[f32; LANES] {
+            self.values[i * self.stride + start..][..LANES]
+                .try_into()
+                .unwrap()
+        };
+
+        match self.metric {
+            Metric::BlockEuclidean { block } => {
+                for block_start in (0..self.dim).step_by(block) {
+                    let mut sum = [0f32; LANES];
+                    let block_end = (block_start + block).min(self.dim);
+                    for (i, query) in (block_start..).zip(&query[block_start..block_end]) {
+                        let column = value(i);
+                        for lane in 0..LANES {
+                            let d = column[lane] - query;
+                            sum[lane] += d * d;
+                        }
+                    }
+                    for lane in 0..LANES {
+                        total[lane] += sum[lane].sqrt();
+                    }
+                }
+            }
+            Metric::Manhattan => {
+                for (i, query) in query.iter().enumerate() {
+                    let column = value(i);
+                    for lane in 0..LANES {
+                        total[lane] += (column[lane] - query).abs();
+                    }
+                }
+            }
+        }
+
+        total.map(|distance| distance * self.output_scale)
+    }
+
+    /// Finds the `k` levels closest to a fingerprint, closest first. A level
+    /// with several points is as close as its closest one.
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<(&str, f32)> {
+        let query = query
+            .iter()
+            .zip(&self.scales)
+            .map(|(value, scale)| value * scale)
+            .collect::<Vec<_>>();
+
+        let mut distances = vec![0f32; self.stride];
+        distances
+            .par_chunks_mut(CHUNK)
+            .enumerate()
+            .for_each(|(chunk, out)| {
+                for (lanes, out) in out.chunks_exact_mut(LANES).enumerate() {
+                    out.copy_from_slice(&self.distances(&query, chunk * CHUNK + lanes * LANES));
+                }
+            });
+
+        // keep the k closest levels in a max-heap, so each point only has to
+        // beat the furthest of them
+        let mut heap = BinaryHeap::with_capacity(k.min(self.names.len()) + 1);
+        let mut push = |candidate: Candidate| {
+            if heap.len() < k {
+                heap.push(candidate);
+            } else if matches!(heap.peek(), Some(furthest) if candidate < *furthest) {
+                heap.pop();
+                heap.push(candidate);
+            }
+        };
+
+        let mut closest: Option<Candidate> = None;
+        for (&level, &distance) in self.owners.iter().zip(&distances) {
+            match &mut closest {
+                Some(candidate) if candidate.level == level => {
+                    candidate.distance = candidate.distance.min(distance);
+                }
+                _ => {
+                    if let Some(candidate) = closest.replace(Candidate { distance, level }) {
+                        push(candidate);
+                    }
+                }
+            }
+        }
+        if let Some(candidate) = closest {
+            push(candidate);
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|candidate| (self.names[candidate.level].as_str(), candidate.distance))
+            .collect()
+    }
+}
@@ -1,43 +1,763 @@
-use byteorder::{ReadBytesExt, LE};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 use colored::{ColoredString, Colorize};
-use image::imageops::FilterType;
-use rustdct::TransformType2And3;
-use serde::Serialize;
+use image::{imageops::FilterType, DynamicImage};
+use lazy_static::lazy_static;
+use rustdct::{DctPlanner, TransformType2And3};
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::Display,
-    io::{self, Cursor, Read},
-    path::Path,
+    io::{self, Cursor, Read, Write},
+    ops::Deref,
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
 };
-use tokio::io::AsyncWriteExt;
 
-use crate::handler::MENTION_REGEX;
+use crate::{
+    index::LevelIndex,
+    kernel::{Kernel, Metric},
+};
 
 pub const IMAGE_DIM: usize = 128;
 pub const NUM_COEFFICIENTS: usize = 10;
 
-#[derive(Debug, Default, Copy, Clone)]
-pub struct Coefficients {
-    pub r: [f32; NUM_COEFFICIENTS],
-    pub g: [f32; NUM_COEFFICIENTS],
-    pub b: [f32; NUM_COEFFICIENTS],
+/// The side length of the top-left block of 2-D DCT coefficients kept per
+/// channel by the `dct` fingerprint, unless another is configured.
+pub const DEFAULT_DCT_BLOCK_SIZE: usize = 4;
+
+/// The DCT block size in use, fixed the first time it's needed.
+static DCT_BLOCK_SIZE: OnceLock<usize> = OnceLock::new();
+
+/// The side length of the bit grid produced by the hash fingerprints.
+const HASH_DIM: usize = 8;
+
+/// The side length images are shrunk to before computing a perceptual hash.
+const PHASH_DIM: usize = 32;
+
+const HUE_BINS: usize = 16;
+const SATURATION_BINS: usize = 8;
+const VALUE_BINS: usize = 8;
+
+lazy_static! {
+    static ref DCT: Dct = Dct {
+        plan: DctPlanner::new().plan_dct2(IMAGE_DIM),
+        block_size: dct_block_size(),
+        zigzag: zigzag(dct_block_size()),
+    };
+    static ref FLAT_DCT: FlatDct = FlatDct {
+        plan: DctPlanner::new().plan_dct2(IMAGE_DIM * IMAGE_DIM),
+    };
+    static ref PHASH: PHash = PHash {
+        plan: DctPlanner::new().plan_dct2(PHASH_DIM),
+    };
 }
 
-fn euclidean_distance(a: &[f32; NUM_COEFFICIENTS], b: &[f32; NUM_COEFFICIENTS]) -> f32 {
-    let mut acc = 0f32;
-    for (i, (a, b)) in a.iter().zip(b).enumerate() {
-        acc += ((NUM_COEFFICIENTS - i) as f32) / (NUM_COEFFICIENTS as f32) * (a - b).powi(2)
+/// The side length of the block of 2-D DCT coefficients the `dct` fingerprint
+/// keeps per channel.
+pub fn dct_block_size() -> usize {
+    *DCT_BLOCK_SIZE.get_or_init(|| DEFAULT_DCT_BLOCK_SIZE)
+}
+
+/// Sets the DCT block size, which can only be done before anything was
+/// fingerprinted or compared.
+pub fn set_dct_block_size(size: usize) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        (1..=IMAGE_DIM).contains(&size),
+        "the DCT block size must be between 1 and {IMAGE_DIM}, not {size}"
+    );
+    DCT_BLOCK_SIZE
+        .set(size)
+        .map_err(|_| anyhow::anyhow!("the DCT block size is already {}", dct_block_size()))
+}
+
+/// The magic bytes at the start of every level database.
+pub const DATABASE_MAGIC: [u8; 4] = *b"SPKY";
+
+/// The current level database format version.
+///
+/// Databases without a header (a bare level count followed by records) are
+/// treated as the legacy format and migrated when read.
+///
+/// - version 1: header and checksum
+/// - version 2: level names are stored as length-prefixed UTF-8
+/// - version 3: levels store multiple reference samples
+/// - version 4: the header records the fingerprint kind
+/// - version 5: levels carry metadata
+/// - version 6: the header records the DCT block size
+pub const DATABASE_VERSION: u16 = 6;
+
+/// The most reference samples kept per level; the oldest is dropped first.
+pub const MAX_SAMPLES: usize = 8;
+
+/// The header of a level database, describing how its coefficients were made.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DatabaseHeader {
+    pub version: u16,
+    pub fingerprint: FingerprintKind,
+    pub image_dim: u32,
+    /// The number of values in each sample.
+    pub num_coefficients: u32,
+    /// The block size of the `dct` fingerprint, whatever the database's is.
+    pub dct_block_size: u32,
+    pub count: u64,
+}
+
+impl DatabaseHeader {
+    /// A header for `count` levels using the current format and parameters.
+    pub fn current(fingerprint: FingerprintKind, count: usize) -> Self {
+        Self {
+            version: DATABASE_VERSION,
+            fingerprint,
+            image_dim: IMAGE_DIM as u32,
+            num_coefficients: fingerprint.num_values() as u32,
+            dct_block_size: dct_block_size() as u32,
+            count: count as u64,
+        }
     }
-    acc.sqrt()
+
+    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if magic != DATABASE_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing level database magic",
+            ));
+        }
+
+        let version = reader.read_u16::<LE>()?;
+        let fingerprint = if version < 4 {
+            FingerprintKind::FlatDct
+        } else {
+            let id = reader.read_u8()?;
+            FingerprintKind::from_id(id).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown fingerprint kind {id}"),
+                )
+            })?
+        };
+
+        Ok(Self {
+            version,
+            fingerprint,
+            image_dim: reader.read_u32::<LE>()?,
+            num_coefficients: reader.read_u32::<LE>()?,
+            dct_block_size: if version < 6 {
+                DEFAULT_DCT_BLOCK_SIZE as u32
+            } else {
+                reader.read_u32::<LE>()?
+            },
+            count: reader.read_u64::<LE>()?,
+        })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&DATABASE_MAGIC)?;
+        writer.write_u16::<LE>(self.version)?;
+        writer.write_u8(self.fingerprint.id())?;
+        writer.write_u32::<LE>(self.image_dim)?;
+        writer.write_u32::<LE>(self.num_coefficients)?;
+        writer.write_u32::<LE>(self.dct_block_size)?;
+        writer.write_u64::<LE>(self.count)?;
+        Ok(())
+    }
+}
+
+/// Why a level database couldn't be read.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The file is damaged: its magic or checksum is wrong, or it's truncated
+    /// or otherwise malformed.
+    Corrupt(anyhow::Error),
+    /// The file is intact, but it's from a newer version or its samples were
+    /// made with other fingerprint parameters than this build's.
+    Incompatible(String),
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Corrupt(err) => write!(f, "{err:#}"),
+            Self::Incompatible(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<io::Error> for DatabaseError {
+    fn from(err: io::Error) -> Self {
+        Self::Corrupt(err.into())
+    }
+}
+
+/// A parsed level database.
+pub struct Database {
+    /// The header, or `None` if the database was in the legacy headerless format.
+    pub header: Option<DatabaseHeader>,
+    pub levels: Vec<Level>,
+    /// Names of levels whose non-ASCII names were mangled by a pre-version 2 writer.
+    pub mangled: Vec<String>,
 }
 
+impl Database {
+    /// Parses a level database, verifying its checksum and fingerprint parameters.
+    pub fn parse(data: &[u8]) -> Result<Self, DatabaseError> {
+        let database = Self::parse_records(data)?;
+        database.check_parameters()?;
+        Ok(database)
+    }
+
+    /// Parses a level database like `parse`, but with samples of whatever size
+    /// it was built with, which this build may not be able to compare. Good
+    /// for reading the names and metadata out of an outdated database.
+    pub fn parse_records(data: &[u8]) -> Result<Self, DatabaseError> {
+        if !data.starts_with(&DATABASE_MAGIC) {
+            return Self::parse_legacy(data).map_err(DatabaseError::Corrupt);
+        }
+
+        let (body, mut checksum) = data.split_at(data.len() - 4);
+        let checksum = checksum.read_u32::<LE>()?;
+        let actual = crc32fast::hash(body);
+        if checksum != actual {
+            return Err(DatabaseError::Corrupt(anyhow::anyhow!(
+                "level database checksum mismatch (expected {checksum:08x}, got {actual:08x})"
+            )));
+        }
+
+        // newer versions may lay out even the header differently
+        let mut cursor = Cursor::new(body);
+        cursor.set_position(DATABASE_MAGIC.len() as u64);
+        let version = cursor.read_u16::<LE>()?;
+        if version > DATABASE_VERSION {
+            return Err(DatabaseError::Incompatible(format!(
+                "level database version {version} is newer than supported version {DATABASE_VERSION}"
+            )));
+        }
+
+        cursor.set_position(0);
+        let header = DatabaseHeader::read(&mut cursor)?;
+        let num_values = header.num_coefficients as usize;
+
+        let mut levels = Vec::with_capacity(header.count as usize);
+        let mut mangled = vec![];
+        for _ in 0..header.count {
+            let (level, was_mangled) = Level::read(&mut cursor, header.version, num_values)?;
+            if was_mangled {
+                mangled.push(level.name.to_owned());
+            }
+            levels.push(level);
+        }
+
+        if cursor.position() != body.len() as u64 {
+            return Err(DatabaseError::Corrupt(anyhow::anyhow!(
+                "level database has trailing data"
+            )));
+        }
+
+        Ok(Self {
+            header: Some(header),
+            levels,
+            mangled,
+        })
+    }
+
+    /// Checks that this build computes the database's fingerprint the same
+    /// way, so that its samples can be compared against new ones.
+    pub fn check_parameters(&self) -> Result<(), DatabaseError> {
+        let Some(header) = self.header else {
+            return Ok(());
+        };
+
+        if header.fingerprint == FingerprintKind::Dct
+            && header.dct_block_size != dct_block_size() as u32
+        {
+            return Err(DatabaseError::Incompatible(format!(
+                "level database was built with a DCT block size of {}, but this \
+                 build uses {}; set `dct_block_size` to match or run `rebuild` \
+                 to recompute it from the saved images",
+                header.dct_block_size,
+                dct_block_size()
+            )));
+        }
+
+        let num_values = header.fingerprint.num_values();
+        if header.image_dim != IMAGE_DIM as u32 || header.num_coefficients != num_values as u32 {
+            return Err(DatabaseError::Incompatible(format!(
+                "level database was built with IMAGE_DIM {} and {} {} values, \
+                 but this build uses {} and {}; run `rebuild` to recompute it \
+                 from the saved images",
+                header.image_dim,
+                header.num_coefficients,
+                header.fingerprint,
+                IMAGE_DIM,
+                num_values
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Parses a legacy database: a bare level count followed by records.
+    fn parse_legacy(data: &[u8]) -> anyhow::Result<Self> {
+        let mut cursor = Cursor::new(data);
+        let count = cursor.read_u64::<LE>()?;
+
+        let mut levels = vec![];
+        let mut mangled = vec![];
+        for _ in 0..count {
+            let (level, was_mangled) =
+                Level::read(&mut cursor, 0, FingerprintKind::FlatDct.num_values())?;
+            if was_mangled {
+                mangled.push(level.name.to_owned());
+            }
+            levels.push(level);
+        }
+
+        Ok(Self {
+            header: None,
+            levels,
+            mangled,
+        })
+    }
+
+    /// The format version this database was stored in, 0 being the legacy format.
+    pub fn version(&self) -> u16 {
+        self.header.map_or(0, |header| header.version)
+    }
+
+    /// The fingerprint this database's samples were computed with.
+    pub fn fingerprint(&self) -> FingerprintKind {
+        self.header
+            .map_or(FingerprintKind::FlatDct, |header| header.fingerprint)
+    }
+
+    /// Serializes levels into the current database format, including the header
+    /// and trailing checksum.
+    pub fn serialize(levels: &LevelSet) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        DatabaseHeader::current(levels.fingerprint, levels.len()).write(&mut buf)?;
+        for level in levels.values() {
+            level.write(&mut buf)?;
+        }
+
+        let checksum = crc32fast::hash(&buf);
+        buf.write_u32::<LE>(checksum)?;
+        Ok(buf)
+    }
+}
+
+/// A level image's fingerprint values: DCT coefficients, hash bits or
+/// histogram bins, depending on the `Fingerprint` that computed them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Coefficients(pub Vec<f32>);
+
 impl Coefficients {
-    pub fn new(data: &[u8], dct: Arc<dyn TransformType2And3<f32>>) -> anyhow::Result<Self> {
-        let img = image::io::Reader::new(Cursor::new(data))
+    /// The mean of a non-empty set of coefficients.
+    pub fn mean<'a>(all: impl ExactSizeIterator<Item = &'a Coefficients>) -> Self {
+        let n = all.len() as f32;
+        let mut mean = vec![];
+        for coefficients in all {
+            mean.resize(coefficients.0.len(), 0f32);
+            for (mean, value) in mean.iter_mut().zip(&coefficients.0) {
+                *mean += value / n;
+            }
+        }
+        Self(mean)
+    }
+
+    pub fn read<R: Read>(reader: &mut R, num_values: usize) -> io::Result<Self> {
+        let mut values = vec![0f32; num_values];
+        reader.read_f32_into::<LE>(&mut values)?;
+        Ok(Self(values))
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for value in &self.0 {
+            writer.write_f32::<LE>(*value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A way of reducing a level image to `Coefficients` that can be compared
+/// against those of other images.
+pub trait Fingerprint: Send + Sync {
+    /// The number of values in every fingerprint this computes.
+    fn num_values(&self) -> usize;
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients;
+
+    /// The distance between two fingerprints; smaller is more alike.
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32;
+}
+
+/// The available fingerprints. Each database records the one its samples were
+/// computed with.
+#[derive(Debug, Default, Copy, Clone, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FingerprintKind {
+    /// the low-frequency 2-D DCT coefficients of each color channel
+    #[default]
+    #[serde(rename = "dct")]
+    Dct,
+    /// a perceptual hash of the grayscale image's low frequencies
+    #[serde(rename = "phash")]
+    PHash,
+    /// a difference hash of horizontally adjacent grayscale pixels
+    #[serde(rename = "dhash")]
+    DHash,
+    /// HSV color histograms compared by chi-square distance
+    #[serde(rename = "histogram")]
+    Histogram,
+    /// HSV color histograms compared by earth mover's distance
+    #[serde(rename = "histogram-emd")]
+    HistogramEmd,
+    /// the first coefficients of a 1-D DCT over each whole color channel, used
+    /// by databases from before the 2-D DCT
+    #[serde(rename = "flat-dct")]
+    FlatDct,
+}
+
+impl FromStr for FingerprintKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "dct" => Self::Dct,
+            "phash" => Self::PHash,
+            "dhash" => Self::DHash,
+            "histogram" => Self::Histogram,
+            "histogram-emd" => Self::HistogramEmd,
+            "flat-dct" => Self::FlatDct,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl Display for FingerprintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Dct => "dct",
+                Self::PHash => "phash",
+                Self::DHash => "dhash",
+                Self::Histogram => "histogram",
+                Self::HistogramEmd => "histogram-emd",
+                Self::FlatDct => "flat-dct",
+            }
+        )
+    }
+}
+
+impl FingerprintKind {
+    pub const ALL: [FingerprintKind; 6] = [
+        FingerprintKind::Dct,
+        FingerprintKind::PHash,
+        FingerprintKind::DHash,
+        FingerprintKind::Histogram,
+        FingerprintKind::HistogramEmd,
+        FingerprintKind::FlatDct,
+    ];
+
+    /// The identifier stored in database headers.
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::FlatDct => 0,
+            Self::PHash => 1,
+            Self::DHash => 2,
+            Self::Histogram => 3,
+            Self::HistogramEmd => 4,
+            Self::Dct => 5,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.id() == id)
+    }
+
+    pub fn fingerprint(&self) -> &'static dyn Fingerprint {
+        match self {
+            Self::Dct => &*DCT,
+            Self::PHash => &*PHASH,
+            Self::DHash => &DHash,
+            Self::Histogram => &Histogram {
+                distance: HistogramDistance::ChiSquare,
+            },
+            Self::HistogramEmd => &Histogram {
+                distance: HistogramDistance::EarthMovers,
+            },
+            Self::FlatDct => &*FLAT_DCT,
+        }
+    }
+
+    pub fn num_values(&self) -> usize {
+        self.fingerprint().num_values()
+    }
+
+    /// Decodes an image and computes its fingerprint.
+    pub fn compute(&self, data: &[u8]) -> anyhow::Result<Coefficients> {
+        let image = image::io::Reader::new(Cursor::new(data))
             .with_guessed_format()?
-            .decode()?
+            .decode()?;
+
+        Ok(self.fingerprint().compute(&image))
+    }
+
+    pub fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        self.fingerprint().distance(a, b)
+    }
+
+    /// How `Kernel` can compute this distance: the metric, a weight per value
+    /// and a factor for the result. `None` if it can't.
+    pub fn kernel_metric(&self) -> Option<(Metric, Vec<f32>, f32)> {
+        match self {
+            Self::Dct => Some(channel_euclidean_kernel(DCT.block_size * DCT.block_size)),
+            Self::FlatDct => Some(channel_euclidean_kernel(NUM_COEFFICIENTS)),
+            Self::PHash | Self::DHash => {
+                Some((Metric::Manhattan, vec![1f32; self.num_values()], 1f32))
+            }
+            Self::Histogram | Self::HistogramEmd => None,
+        }
+    }
+
+    /// How many coefficients each color channel has, for fingerprints whose
+    /// distance is a weighted sum over channels. `None` for the rest.
+    pub fn channel_size(&self) -> Option<usize> {
+        match self {
+            Self::Dct => Some(DCT.block_size * DCT.block_size),
+            Self::FlatDct => Some(NUM_COEFFICIENTS),
+            Self::PHash | Self::DHash | Self::Histogram | Self::HistogramEmd => None,
+        }
+    }
+
+    /// Whether the distance obeys the triangle inequality, which `LevelIndex`
+    /// needs to prune its search. Chi-square doesn't.
+    pub fn is_metric(&self) -> bool {
+        !matches!(self, Self::Histogram)
+    }
+}
+
+/// The weight of each of `n` values in `euclidean_distance`; earlier (lower
+/// frequency) values count for more.
+fn euclidean_weights(n: usize) -> impl Iterator<Item = f32> + Clone {
+    (0..n).map(move |i| ((n - i) as f32) / (n as f32))
+}
+
+fn euclidean_distance(a: &[f32], b: &[f32]) -> f32 {
+    let mut acc = 0f32;
+    for ((a, b), weight) in a.iter().zip(b).zip(euclidean_weights(a.len())) {
+        acc += weight * (a - b).powi(2)
+    }
+    acc.sqrt()
+}
+
+/// The kernel parameters for `euclidean_distance` over each of three
+/// `n`-value channels, averaged.
+fn channel_euclidean_kernel(n: usize) -> (Metric, Vec<f32>, f32) {
+    (
+        Metric::BlockEuclidean { block: n },
+        euclidean_weights(n).cycle().take(3 * n).collect(),
+        1f32 / 3f32,
+    )
+}
+
+/// How much each value counts in the distance of a fingerprint made of one
+/// block of coefficients per color channel. Fingerprints have built-in
+/// weights; these replace them, and can be tuned by `tune`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Weights {
+    /// the weight of each coefficient's squared difference within a channel
+    pub coefficients: Vec<f32>,
+    /// the weight of each channel's distance, in `r`, `g`, `b` order
+    pub channels: [f32; 3],
+}
+
+impl Weights {
+    /// The weights a fingerprint uses on its own. `None` if its distance
+    /// can't be weighted.
+    pub fn default_for(fingerprint: FingerprintKind) -> Option<Self> {
+        let n = fingerprint.channel_size()?;
+        Some(Self {
+            coefficients: euclidean_weights(n).collect(),
+            channels: [1f32 / 3f32; 3],
+        })
+    }
+
+    /// Whether these weights can be used with a fingerprint.
+    pub fn fits(&self, fingerprint: FingerprintKind) -> bool {
+        fingerprint.channel_size() == Some(self.coefficients.len())
+    }
+
+    fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        let n = self.coefficients.len();
+        a.chunks_exact(n)
+            .zip(b.chunks_exact(n))
+            .zip(self.channels)
+            .map(|((a, b), channel)| {
+                let mut acc = 0f32;
+                for ((a, b), weight) in a.iter().zip(b).zip(&self.coefficients) {
+                    acc += weight * (a - b).powi(2);
+                }
+                channel * acc.sqrt()
+            })
+            .sum()
+    }
+
+    /// The kernel parameters for `distance`. The channel weights multiply a
+    /// square root, so they're folded into the coefficients squared.
+    fn kernel_metric(&self) -> (Metric, Vec<f32>, f32) {
+        (
+            Metric::BlockEuclidean {
+                block: self.coefficients.len(),
+            },
+            self.channels
+                .iter()
+                .flat_map(|channel| self.coefficients.iter().map(move |w| w * channel * channel))
+                .collect(),
+            1f32,
+        )
+    }
+}
+
+/// How two fingerprints of a `LevelSet` are compared: the fingerprint's own
+/// distance, or a weighted one if the set has weights.
+#[derive(Clone)]
+pub struct Measure {
+    fingerprint: FingerprintKind,
+    weights: Option<Arc<Weights>>,
+}
+
+impl Measure {
+    pub fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        match &self.weights {
+            Some(weights) => weights.distance(&a.0, &b.0),
+            None => self.fingerprint.distance(a, b),
+        }
+    }
+
+    /// Like `FingerprintKind::kernel_metric`, but with the weights.
+    pub fn kernel_metric(&self) -> Option<(Metric, Vec<f32>, f32)> {
+        match &self.weights {
+            Some(weights) => Some(weights.kernel_metric()),
+            None => self.fingerprint.kernel_metric(),
+        }
+    }
+}
+
+/// The number of differing bits between two hashes (or, for centroids, the
+/// summed difference between bit frequencies).
+fn hamming_distance(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+}
+
+/// Resizes an image and returns its row-major grayscale pixels in `0..=1`.
+fn grayscale(image: &DynamicImage, width: usize, height: usize) -> Vec<f32> {
+    image
+        .resize_exact(width as u32, height as u32, FilterType::Triangle)
+        .into_luma8()
+        .iter()
+        .map(|&p| p as f32 / 255f32)
+        .collect()
+}
+
+/// An in-place separable 2-D DCT-II over a row-major `dim`×`dim` buffer,
+/// using a plan for `dim`-length transforms.
+fn dct_2d(plan: &dyn TransformType2And3<f32>, buffer: &mut [f32], dim: usize) {
+    for row in buffer.chunks_exact_mut(dim) {
+        plan.process_dct2(row);
+    }
+
+    let mut column = vec![0f32; dim];
+    for x in 0..dim {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = buffer[y * dim + x];
+        }
+
+        plan.process_dct2(&mut column);
+
+        for (y, value) in column.iter().enumerate() {
+            buffer[y * dim + x] = *value;
+        }
+    }
+}
+
+/// The `(x, y)` positions of a `dim`×`dim` block in JPEG zig-zag order, so
+/// that lower frequencies come first.
+fn zigzag(dim: usize) -> Vec<(usize, usize)> {
+    let mut order = Vec::with_capacity(dim * dim);
+    for diagonal in 0..2 * dim - 1 {
+        let cells = (0..dim)
+            .filter_map(|y| Some((diagonal.checked_sub(y).filter(|&x| x < dim)?, y)))
+            .collect::<Vec<_>>();
+
+        // even diagonals run bottom-left to top-right
+        if diagonal % 2 == 0 {
+            order.extend(cells.into_iter().rev());
+        } else {
+            order.extend(cells);
+        }
+    }
+    order
+}
+
+/// The top-left `block_size`×`block_size` block of each color channel's 2-D
+/// DCT coefficients in zig-zag order, in `r`, `g`, `b` order.
+pub struct Dct {
+    plan: Arc<dyn TransformType2And3<f32>>,
+    block_size: usize,
+    zigzag: Vec<(usize, usize)>,
+}
+
+impl Fingerprint for Dct {
+    fn num_values(&self) -> usize {
+        3 * self.block_size * self.block_size
+    }
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients {
+        let img = image
+            .resize_exact(IMAGE_DIM as u32, IMAGE_DIM as u32, FilterType::Triangle)
+            .into_rgb32f();
+
+        let mut values = Vec::with_capacity(self.num_values());
+        for channel in 0..3 {
+            let mut buffer = img.pixels().map(|p| p.0[channel]).collect::<Vec<_>>();
+            dct_2d(&*self.plan, &mut buffer, IMAGE_DIM);
+
+            values.extend(self.zigzag.iter().map(|&(x, y)| buffer[y * IMAGE_DIM + x]));
+        }
+
+        Coefficients(values)
+    }
+
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        let n = self.block_size * self.block_size;
+        let channels =
+            a.0.chunks_exact(n)
+                .zip(b.0.chunks_exact(n))
+                .map(|(a, b)| euclidean_distance(a, b));
+
+        channels.sum::<f32>() / 3f32
+    }
+}
+
+/// The first `NUM_COEFFICIENTS` coefficients of a 1-D DCT over each whole
+/// row-major color channel, in `r`, `g`, `b` order.
+pub struct FlatDct {
+    plan: Arc<dyn TransformType2And3<f32>>,
+}
+
+impl Fingerprint for FlatDct {
+    fn num_values(&self) -> usize {
+        3 * NUM_COEFFICIENTS
+    }
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients {
+        let img = image
             .resize_exact(IMAGE_DIM as u32, IMAGE_DIM as u32, FilterType::Triangle)
             .into_rgb32f()
             .iter()
@@ -53,22 +773,560 @@ impl Coefficients {
             b[i] = chunk[2];
         }
 
-        dct.process_dct2(&mut r);
-        dct.process_dct2(&mut g);
-        dct.process_dct2(&mut b);
+        self.plan.process_dct2(&mut r);
+        self.plan.process_dct2(&mut g);
+        self.plan.process_dct2(&mut b);
 
-        Ok(Self {
-            r: r[0..NUM_COEFFICIENTS].try_into()?,
-            g: g[0..NUM_COEFFICIENTS].try_into()?,
-            b: b[0..NUM_COEFFICIENTS].try_into()?,
+        Coefficients(
+            [r, g, b]
+                .iter()
+                .flat_map(|channel| channel[0..NUM_COEFFICIENTS].iter().copied())
+                .collect(),
+        )
+    }
+
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        let channels =
+            a.0.chunks_exact(NUM_COEFFICIENTS)
+                .zip(b.0.chunks_exact(NUM_COEFFICIENTS))
+                .map(|(a, b)| euclidean_distance(a, b));
+
+        // an average unless tuned `Weights` say otherwise
+        channels.sum::<f32>() / 3f32
+    }
+}
+
+/// A perceptual hash: one bit per low-frequency 2-D DCT coefficient of the
+/// grayscale image, set if it is above the median.
+pub struct PHash {
+    plan: Arc<dyn TransformType2And3<f32>>,
+}
+
+impl Fingerprint for PHash {
+    fn num_values(&self) -> usize {
+        HASH_DIM * HASH_DIM
+    }
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients {
+        let mut pixels = grayscale(image, PHASH_DIM, PHASH_DIM);
+        dct_2d(&*self.plan, &mut pixels, PHASH_DIM);
+
+        let low = pixels
+            .chunks_exact(PHASH_DIM)
+            .take(HASH_DIM)
+            .flat_map(|row| row[0..HASH_DIM].iter().copied())
+            .collect::<Vec<_>>();
+
+        // the DC term dwarfs everything else, so leave it out of the median
+        let mut sorted = low[1..].to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let median = sorted[sorted.len() / 2];
+
+        Coefficients(
+            low.iter()
+                .map(|&v| if v > median { 1f32 } else { 0f32 })
+                .collect(),
+        )
+    }
+
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        hamming_distance(&a.0, &b.0)
+    }
+}
+
+/// A difference hash: one bit per pair of horizontally adjacent grayscale
+/// pixels, set if the left one is brighter.
+pub struct DHash;
+
+impl Fingerprint for DHash {
+    fn num_values(&self) -> usize {
+        HASH_DIM * HASH_DIM
+    }
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients {
+        let pixels = grayscale(image, HASH_DIM + 1, HASH_DIM);
+
+        Coefficients(
+            pixels
+                .chunks_exact(HASH_DIM + 1)
+                .flat_map(|row| row.windows(2))
+                .map(|pair| if pair[0] > pair[1] { 1f32 } else { 0f32 })
+                .collect(),
+        )
+    }
+
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        hamming_distance(&a.0, &b.0)
+    }
+}
+
+enum HistogramDistance {
+    ChiSquare,
+    EarthMovers,
+}
+
+/// Normalized hue, saturation and value histograms, concatenated.
+pub struct Histogram {
+    distance: HistogramDistance,
+}
+
+/// Converts an RGB pixel to hue, saturation and value, all in `0..=1`.
+fn rgb_to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0f32 {
+        0f32
+    } else if max == r {
+        ((g - b) / delta).rem_euclid(6f32)
+    } else if max == g {
+        (b - r) / delta + 2f32
+    } else {
+        (r - g) / delta + 4f32
+    } / 6f32;
+
+    let saturation = if max == 0f32 { 0f32 } else { delta / max };
+    [hue, saturation, max]
+}
+
+impl Fingerprint for Histogram {
+    fn num_values(&self) -> usize {
+        HUE_BINS + SATURATION_BINS + VALUE_BINS
+    }
+
+    fn compute(&self, image: &DynamicImage) -> Coefficients {
+        let img = image
+            .resize_exact(IMAGE_DIM as u32, IMAGE_DIM as u32, FilterType::Triangle)
+            .into_rgb32f();
+
+        let mut bins = vec![0f32; self.num_values()];
+        let weight = 1f32 / (IMAGE_DIM * IMAGE_DIM) as f32;
+        for pixel in img.pixels() {
+            let [h, s, v] = rgb_to_hsv(pixel.0);
+            let bin = |value: f32, n: usize| ((value * n as f32) as usize).min(n - 1);
+
+            bins[bin(h, HUE_BINS)] += weight;
+            bins[HUE_BINS + bin(s, SATURATION_BINS)] += weight;
+            bins[HUE_BINS + SATURATION_BINS + bin(v, VALUE_BINS)] += weight;
+        }
+
+        Coefficients(bins)
+    }
+
+    fn distance(&self, a: &Coefficients, b: &Coefficients) -> f32 {
+        match self.distance {
+            HistogramDistance::ChiSquare => {
+                0.5 * a
+                    .0
+                    .iter()
+                    .zip(&b.0)
+                    .filter(|(a, b)| *a + *b > 0f32)
+                    .map(|(a, b)| (a - b).powi(2) / (a + b))
+                    .sum::<f32>()
+            }
+
+            // the 1-D EMD of each histogram is the area between their CDFs
+            // (hue is treated as linear rather than circular)
+            HistogramDistance::EarthMovers => {
+                let mut acc = 0f32;
+                let mut start = 0;
+                for n in [HUE_BINS, SATURATION_BINS, VALUE_BINS] {
+                    let mut cdf = 0f32;
+                    for i in start..start + n {
+                        cdf += a.0[i] - b.0[i];
+                        acc += cdf.abs();
+                    }
+                    start += n;
+                }
+                acc
+            }
+        }
+    }
+}
+
+/// How a level's samples are compared against a guess.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleMatch {
+    /// the distance to the closest sample
+    #[default]
+    Nearest,
+    /// the distance to the mean of all samples
+    Centroid,
+}
+
+impl FromStr for SampleMatch {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "nearest" => Self::Nearest,
+            "centroid" => Self::Centroid,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// All known levels of one difficulty, along with the fingerprint their
+/// samples were computed with.
+#[derive(Default)]
+pub struct LevelSet {
+    pub fingerprint: FingerprintKind,
+    pub levels: HashMap<String, Level>,
+    /// The distance under which a guess is probably a level we know, learned
+    /// from the samples by `confidence::learn_threshold`. Not saved.
+    pub threshold: Option<f32>,
+    /// How many samples were added since the threshold was learned. Not saved.
+    pub unthresholded: usize,
+    /// Speeds up searches once built with `build_index`. Not saved.
+    pub index: Option<LevelIndex>,
+    /// Speeds up exact searches once built with `build_kernel`. Not saved.
+    pub kernel: Option<(SampleMatch, Kernel)>,
+    /// Replaces the fingerprint's built-in weights, if it has any. Saved next
+    /// to the database by `save_weights`.
+    pub weights: Option<Arc<Weights>>,
+    /// Other accepted spellings of level names, normalised, mapped to the
+    /// level they name.
+    pub aliases: BTreeMap<String, String>,
+    /// Level names, normalised, mapped to the level, built when first needed
+    /// and kept up to date by `insert` and `remove`. Not saved.
+    pub normalized_names: OnceLock<HashMap<String, String>>,
+}
+
+impl LevelSet {
+    /// The name of the level a name refers to: the level by that name, the
+    /// one it's an alias of, or one whose name normalises the same.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        if let Some((name, _)) = self.levels.get_key_value(name) {
+            return Some(name);
+        }
+
+        let normalized = normalize_name(name);
+        if let Some((name, _)) = self
+            .aliases
+            .get(&normalized)
+            .and_then(|level| self.levels.get_key_value(level))
+        {
+            return Some(name);
+        }
+
+        self.normalized_names().get(&normalized).map(String::as_str)
+    }
+
+    /// Level names, normalised, mapped to the level.
+    fn normalized_names(&self) -> &HashMap<String, String> {
+        self.normalized_names.get_or_init(|| {
+            self.levels
+                .keys()
+                .map(|name| (normalize_name(name), name.to_owned()))
+                .collect()
         })
     }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Level> {
+        self.levels.get_mut(name)
+    }
+
+    /// Adds a level, or replaces the one by the same name.
+    pub fn insert(&mut self, name: String, level: Level) -> Option<Level> {
+        if let Some(normalized_names) = self.normalized_names.get_mut() {
+            normalized_names.insert(normalize_name(&name), name.to_owned());
+        }
+        self.levels.insert(name, level)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Level> {
+        let level = self.levels.remove(name)?;
+
+        // another level whose name normalises the same takes over
+        if let Some(normalized_names) = self.normalized_names.get_mut() {
+            let normalized = normalize_name(name);
+            if normalized_names
+                .get(&normalized)
+                .is_some_and(|level| level == name)
+            {
+                match self
+                    .levels
+                    .keys()
+                    .find(|other| normalize_name(other) == normalized)
+                {
+                    Some(other) => normalized_names.insert(normalized, other.to_owned()),
+                    None => normalized_names.remove(&normalized),
+                };
+            }
+        }
+
+        Some(level)
+    }
+
+    /// Lets `alias` resolve to `level`, returning the level's name.
+    pub fn add_alias(&mut self, alias: &str, level: &str) -> anyhow::Result<String> {
+        let level = self
+            .resolve(level)
+            .ok_or_else(|| anyhow::anyhow!("there is no level named {level}"))?
+            .to_owned();
+
+        let alias = normalize_name(alias);
+        if let Some(other) = self.normalized_names().get(&alias) {
+            anyhow::bail!("{alias} is already the name of {other}");
+        }
+
+        self.aliases.insert(alias, level.to_owned());
+        Ok(level)
+    }
+
+    /// Removes an alias, returning the level it was of.
+    pub fn remove_alias(&mut self, alias: &str) -> Option<String> {
+        self.aliases.remove(&normalize_name(alias))
+    }
+
+    /// Points aliases of a renamed or merged level at `to`, or with `None`
+    /// drops them along with the level.
+    pub fn retarget_aliases(&mut self, from: &str, to: Option<&str>) {
+        match to {
+            Some(to) => {
+                for level in self.aliases.values_mut() {
+                    if level == from {
+                        *level = to.to_owned();
+                    }
+                }
+            }
+            None => self.aliases.retain(|_, level| level != from),
+        }
+    }
+
+    /// Renames a level, keeping its aliases. Fails if `to` already refers to
+    /// another level, which would be lost; those are for `dedupe` to merge.
+    pub fn rename(&mut self, from: &str, to: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.levels.contains_key(from),
+            "there is no level named {from}"
+        );
+        if let Some(other) = self.resolve(to).filter(|other| *other != from) {
+            anyhow::bail!("{to} is already {other}, merge them with dedupe instead");
+        }
+
+        let mut level = self.remove(from).unwrap();
+        level.name = to.to_owned();
+        self.insert(to.to_owned(), level);
+        self.remove_alias(to);
+        self.retarget_aliases(from, Some(to));
+        Ok(())
+    }
+
+    /// A copy of the levels and how they're compared, without the index or
+    /// kernel, to work on without holding up searches.
+    pub fn snapshot(&self) -> LevelSet {
+        LevelSet {
+            fingerprint: self.fingerprint,
+            levels: self.levels.clone(),
+            weights: self.weights.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn measure(&self) -> Measure {
+        Measure {
+            fingerprint: self.fingerprint,
+            weights: self.weights.clone(),
+        }
+    }
+
+    pub fn build_index(&mut self, mode: SampleMatch) {
+        self.index = LevelIndex::build(self, mode);
+    }
+
+    /// Lays out every sample (or centroid) for `Kernel`, if the fingerprint's
+    /// distance can be computed by it.
+    pub fn build_kernel(&mut self, mode: SampleMatch) {
+        self.kernel = self
+            .measure()
+            .kernel_metric()
+            .map(|(metric, weights, output_scale)| {
+                let levels = self.levels.values().map(|level| {
+                    let points = match mode {
+                        SampleMatch::Nearest => level
+                            .samples
+                            .iter()
+                            .map(|sample| sample.0.as_slice())
+                            .collect(),
+                        SampleMatch::Centroid => vec![level.centroid.0.as_slice()],
+                    };
+                    (level.name.to_owned(), points)
+                });
+
+                (mode, Kernel::new(metric, &weights, output_scale, levels))
+            });
+    }
+
+    /// Brings the index and kernel up to date after a level was added, changed
+    /// or removed.
+    pub fn reindex(&mut self, name: &str) {
+        // the kernel is a flat copy, which is quicker to redo than to patch
+        if let Some((mode, _)) = self.kernel {
+            self.build_kernel(mode);
+        }
+
+        let Some(index) = &mut self.index else {
+            return;
+        };
+
+        index.update(name, self.levels.get(name));
+        if index.needs_rebuild() {
+            let mode = index.mode();
+            self.build_index(mode);
+        }
+    }
 }
 
+/// Levels are only read through this: adding or removing them goes through
+/// `insert` and `remove`, which keep `normalized_names` up to date.
+impl Deref for LevelSet {
+    type Target = HashMap<String, Level>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.levels
+    }
+}
+
+#[derive(Clone)]
 pub struct Level {
     pub name: String,
     pub difficulty: LevelDifficulty,
-    pub coefficients: Coefficients,
+    /// Reference samples (screenshots) of this level, oldest first.
+    pub samples: Vec<Coefficients>,
+    /// The mean of `samples`.
+    pub centroid: Coefficients,
+    pub metadata: LevelMetadata,
+}
+
+/// What we know about a level besides what it looks like. Databases before
+/// version 5 didn't record any of it.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LevelMetadata {
+    /// the level's ID in Geometry Dash
+    pub level_id: Option<u64>,
+    pub creator: Option<String>,
+    /// when it was first the answer to a round, in seconds since the Unix epoch
+    pub first_seen: Option<u64>,
+    /// when it was last the answer to a round, in seconds since the Unix epoch
+    pub last_seen: Option<u64>,
+    /// how many rounds it was the answer to
+    pub times_seen: u32,
+    /// how many of those rounds our best guess was right
+    pub times_guessed: u32,
+}
+
+/// The current time in seconds since the Unix epoch, as `LevelMetadata`
+/// stores it.
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Which optional `LevelMetadata` fields a record has.
+const METADATA_LEVEL_ID: u8 = 1 << 0;
+const METADATA_CREATOR: u8 = 1 << 1;
+const METADATA_FIRST_SEEN: u8 = 1 << 2;
+const METADATA_LAST_SEEN: u8 = 1 << 3;
+
+impl LevelMetadata {
+    /// Counts a round the level was the answer to, ending at `now`.
+    pub fn record_round(&mut self, now: u64, guessed: bool) {
+        self.first_seen.get_or_insert(now);
+        self.last_seen = Some(now);
+        self.times_seen += 1;
+        if guessed {
+            self.times_guessed += 1;
+        }
+    }
+
+    /// Folds in the metadata of a duplicate of the same level.
+    pub fn absorb(&mut self, other: LevelMetadata) {
+        self.level_id = self.level_id.or(other.level_id);
+        self.creator = self.creator.take().or(other.creator);
+        self.first_seen = match (self.first_seen, other.first_seen) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.last_seen = self.last_seen.max(other.last_seen);
+        self.times_seen += other.times_seen;
+        self.times_guessed += other.times_guessed;
+    }
+
+    fn read(cursor: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        let flags = cursor.read_u8()?;
+        let level_id = (flags & METADATA_LEVEL_ID != 0)
+            .then(|| cursor.read_u64::<LE>())
+            .transpose()?;
+        let creator = (flags & METADATA_CREATOR != 0)
+            .then(|| {
+                let len = cursor.read_u16::<LE>()?;
+                let mut creator = vec![0u8; len as usize];
+                cursor.read_exact(&mut creator)?;
+                String::from_utf8(creator)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+            })
+            .transpose()?;
+        let first_seen = (flags & METADATA_FIRST_SEEN != 0)
+            .then(|| cursor.read_u64::<LE>())
+            .transpose()?;
+        let last_seen = (flags & METADATA_LAST_SEEN != 0)
+            .then(|| cursor.read_u64::<LE>())
+            .transpose()?;
+
+        Ok(Self {
+            level_id,
+            creator,
+            first_seen,
+            last_seen,
+            times_seen: cursor.read_u32::<LE>()?,
+            times_guessed: cursor.read_u32::<LE>()?,
+        })
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let mut flags = 0;
+        for (present, flag) in [
+            (self.level_id.is_some(), METADATA_LEVEL_ID),
+            (self.creator.is_some(), METADATA_CREATOR),
+            (self.first_seen.is_some(), METADATA_FIRST_SEEN),
+            (self.last_seen.is_some(), METADATA_LAST_SEEN),
+        ] {
+            if present {
+                flags |= flag;
+            }
+        }
+        writer.write_u8(flags)?;
+
+        if let Some(level_id) = self.level_id {
+            writer.write_u64::<LE>(level_id)?;
+        }
+        if let Some(creator) = &self.creator {
+            let len: u16 = creator.len().try_into().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("creator name is too long ({} bytes)", creator.len()),
+                )
+            })?;
+            writer.write_u16::<LE>(len)?;
+            writer.write_all(creator.as_bytes())?;
+        }
+        if let Some(first_seen) = self.first_seen {
+            writer.write_u64::<LE>(first_seen)?;
+        }
+        if let Some(last_seen) = self.last_seen {
+            writer.write_u64::<LE>(last_seen)?;
+        }
+
+        writer.write_u32::<LE>(self.times_seen)?;
+        writer.write_u32::<LE>(self.times_guessed)?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
@@ -124,6 +1382,13 @@ impl Display for LevelDifficulty {
 }
 
 impl LevelDifficulty {
+    pub const ALL: [LevelDifficulty; 4] = [
+        LevelDifficulty::Easy,
+        LevelDifficulty::Medium,
+        LevelDifficulty::Hard,
+        LevelDifficulty::Legendary,
+    ];
+
     pub fn colorize(&self, s: impl Colorize) -> ColoredString {
         match self {
             Self::Easy => s.green(),
@@ -150,99 +1415,526 @@ impl LevelDifficulty {
             Self::Legendary => "legendary.bin",
         }
     }
+
+    /// Where the winning screenshot of a level is saved.
+    pub fn image_path(&self, name: &str) -> String {
+        format!("levels/{}/{}.png", self.directory(), name)
+    }
+
+    /// The previous generation of this difficulty's database, kept by `save_levels`.
+    pub fn backup_filename(&self) -> String {
+        format!("{}.bak", self.filename())
+    }
+
+    /// Where other spellings of level names are saved, by `save_levels`.
+    pub fn aliases_filename(&self) -> String {
+        format!("{}.aliases.json", self.directory())
+    }
+
+    /// Where tuned distance weights are saved.
+    pub fn weights_filename(&self) -> String {
+        format!("{}.weights.json", self.directory())
+    }
 }
 
-pub async fn read_levels(difficulty: LevelDifficulty) -> HashMap<String, Level> {
-    // create image difficulty folders
-    tokio::fs::create_dir_all(format!("levels/{}", difficulty.directory()))
-        .await
-        .unwrap();
+/// Reads the tuned weights of a difficulty, if it has any that fit its
+/// fingerprint.
+pub fn read_weights(difficulty: LevelDifficulty, fingerprint: FingerprintKind) -> Option<Weights> {
+    let filename = difficulty.weights_filename();
+    let data = std::fs::read(&filename).ok()?;
+    let weights: Weights = serde_json::from_slice(&data)
+        .unwrap_or_else(|err| panic!("failed to read {filename}: {err}"));
 
-    let mut levels = vec![];
+    if !weights.fits(fingerprint) {
+        println!(
+            "{} ignoring {}, its weights don't fit the {} fingerprint",
+            "warning!".yellow().bold(),
+            filename,
+            fingerprint
+        );
+        return None;
+    }
 
-    if Path::new(difficulty.filename()).exists() {
-        let mut cursor = Cursor::new(tokio::fs::read(difficulty.filename()).await.unwrap());
-        let count = cursor.read_u64::<LE>().unwrap();
-        for _ in 0..count {
-            let level = Level::read(&mut cursor).unwrap();
-            if level.name.starts_with("s?") || MENTION_REGEX.is_match(&level.name) {
-                continue;
-            }
+    Some(weights)
+}
 
-            levels.push((level.name.to_owned(), level));
-        }
-        println!("read in {} {} levels", levels.len(), difficulty);
+pub fn save_weights(difficulty: LevelDifficulty, weights: &Weights) -> io::Result<()> {
+    std::fs::write(
+        difficulty.weights_filename(),
+        serde_json::to_string_pretty(weights).unwrap(),
+    )
+}
+
+/// The canonical form of a level name: case folded, without punctuation, and
+/// with runs of whitespace collapsed to single spaces. Names made only of
+/// punctuation are just case folded.
+pub fn normalize_name(name: &str) -> String {
+    let folded = caseless::default_case_fold_str(name);
+    let normalized = folded
+        .split(|c: char| !c.is_alphanumeric() && !c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if normalized.is_empty() {
+        folded.trim().to_owned()
+    } else {
+        normalized
     }
+}
 
-    levels.into_iter().collect::<HashMap<_, _>>()
+/// Mangles a name the way databases before version 2 stored it, truncating
+/// each `char` to a single byte.
+pub fn mangle_name(name: &str) -> String {
+    name.chars().map(|c| c as u8 as char).collect()
+}
+
+/// The size of a pre-version 2 record after its name: difficulty and coefficients.
+const LEGACY_TAIL_LEN: usize = 1 + 3 * NUM_COEFFICIENTS * 4;
+
+/// Whether a pre-version 2 record's difficulty and coefficients plausibly
+/// start at `pos`.
+fn is_legacy_record_tail(data: &[u8], pos: usize) -> bool {
+    let Some(tail) = data.get(pos..pos + LEGACY_TAIL_LEN) else {
+        return false;
+    };
+
+    let coeffs = tail[1..]
+        .chunks_exact(4)
+        .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+        .collect::<Vec<_>>();
+
+    tail[0] <= 3
+        && coeffs.iter().all(|c| c.is_finite() && c.abs() < 1e6)
+        && coeffs
+            .chunks_exact(NUM_COEFFICIENTS)
+            .all(|channel| channel[0] >= 0f32)
+}
+
+/// Finds how many name bytes were actually written for the pre-version 2
+/// record starting at `pos`.
+///
+/// These names were written as their UTF-8 byte length followed by one byte
+/// per `char`, so non-ASCII names are shorter than their length prefix claims.
+/// The shortest length followed by a plausible record tail (and, with
+/// `lookahead`, a plausible next record) wins.
+fn find_legacy_name_len(data: &[u8], pos: usize, lookahead: bool) -> Option<usize> {
+    let name_len = *data.get(pos)? as usize;
+    let start = pos + 1;
+
+    (name_len.div_ceil(4)..=name_len).find(|len| {
+        let next = start + len + LEGACY_TAIL_LEN;
+        is_legacy_record_tail(data, start + len)
+            && (!lookahead
+                || next == data.len()
+                || find_legacy_name_len(data, next, false).is_some())
+    })
+}
+
+/// Reads a pre-version 2 level name, returning it decoded as Latin-1 and
+/// whether it was mangled.
+fn read_legacy_name(cursor: &mut Cursor<&[u8]>) -> io::Result<(String, bool)> {
+    let pos = cursor.position() as usize;
+    let data = *cursor.get_ref();
+    let name_len = cursor.read_u8()? as usize;
+
+    let written = find_legacy_name_len(data, pos, true).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "could not find the end of a legacy level name",
+        )
+    })?;
+
+    let name = data[pos + 1..pos + 1 + written]
+        .iter()
+        .map(|&b| b as char)
+        .collect();
+    cursor.set_position((pos + 1 + written) as u64);
+
+    Ok((name, written != name_len))
 }
 
 impl Level {
-    pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let name_len = reader.read_u8()?;
-        let mut name = String::new();
+    pub fn new(name: String, difficulty: LevelDifficulty, coefficients: Coefficients) -> Self {
+        Self {
+            name,
+            difficulty,
+            samples: vec![coefficients.clone()],
+            centroid: coefficients,
+            metadata: LevelMetadata::default(),
+        }
+    }
 
-        for _ in 0..name_len {
-            name.push(reader.read_u8()? as char);
+    /// Adds a reference sample, dropping the oldest once there are `MAX_SAMPLES`.
+    pub fn add_sample(&mut self, coefficients: Coefficients) {
+        if self.samples.len() >= MAX_SAMPLES {
+            self.samples.remove(0);
         }
 
-        let difficulty = match reader.read_u8()? {
+        self.samples.push(coefficients);
+        self.centroid = Coefficients::mean(self.samples.iter());
+    }
+
+    /// Reads a level record stored in the given database format version,
+    /// returning whether its name was mangled by an older writer.
+    pub fn read(
+        cursor: &mut Cursor<&[u8]>,
+        version: u16,
+        num_values: usize,
+    ) -> io::Result<(Self, bool)> {
+        let (name, mangled) = if version < 2 {
+            read_legacy_name(cursor)?
+        } else {
+            let name_len = cursor.read_u16::<LE>()?;
+            let mut name = vec![0u8; name_len as usize];
+            cursor.read_exact(&mut name)?;
+
+            let name = String::from_utf8(name)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            (name, false)
+        };
+
+        let difficulty = match cursor.read_u8()? {
             1 => LevelDifficulty::Medium,
             2 => LevelDifficulty::Hard,
             3 => LevelDifficulty::Legendary,
             _ => LevelDifficulty::Easy,
         };
 
-        let mut r = [0f32; NUM_COEFFICIENTS];
-        let mut g = [0f32; NUM_COEFFICIENTS];
-        let mut b = [0f32; NUM_COEFFICIENTS];
-        reader.read_f32_into::<LE>(&mut r)?;
-        reader.read_f32_into::<LE>(&mut g)?;
-        reader.read_f32_into::<LE>(&mut b)?;
+        let num_samples = if version < 3 { 1 } else { cursor.read_u8()? };
+        if num_samples == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("level {name} has no samples"),
+            ));
+        }
 
-        Ok(Self {
-            name,
-            difficulty,
-            coefficients: Coefficients { r, g, b },
-        })
+        let samples = (0..num_samples)
+            .map(|_| Coefficients::read(cursor, num_values))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut samples = samples.into_iter();
+        let mut level = Self::new(name, difficulty, samples.next().unwrap());
+        for sample in samples {
+            level.add_sample(sample);
+        }
+
+        if version >= 5 {
+            level.metadata = LevelMetadata::read(cursor)?;
+        }
+
+        Ok((level, mangled))
     }
 
-    pub async fn write(&self, writer: &mut tokio::fs::File) -> io::Result<()> {
-        writer.write_u8(self.name.len() as u8).await?;
-        for c in self.name.chars() {
-            writer.write_u8(c as u8).await?;
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let name_len: u16 = self.name.len().try_into().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("level name is too long ({} bytes)", self.name.len()),
+            )
+        })?;
+        writer.write_u16::<LE>(name_len)?;
+        writer.write_all(self.name.as_bytes())?;
+
+        writer.write_u8(match self.difficulty {
+            LevelDifficulty::Easy => 0,
+            LevelDifficulty::Medium => 1,
+            LevelDifficulty::Hard => 2,
+            LevelDifficulty::Legendary => 3,
+        })?;
+
+        writer.write_u8(self.samples.len() as u8)?;
+        for sample in &self.samples {
+            sample.write(writer)?;
         }
 
-        writer
-            .write_u8(match self.difficulty {
-                LevelDifficulty::Easy => 0,
-                LevelDifficulty::Medium => 1,
-                LevelDifficulty::Hard => 2,
-                LevelDifficulty::Legendary => 3,
-            })
-            .await?;
+        self.metadata.write(writer)
+    }
 
-        for coeff in &self.coefficients.r {
-            writer.write_f32_le(*coeff).await?;
+    pub fn distance_to(&self, other: &Coefficients, measure: &Measure, mode: SampleMatch) -> f32 {
+        match mode {
+            SampleMatch::Nearest => self
+                .samples
+                .iter()
+                .map(|sample| measure.distance(sample, other))
+                .fold(f32::INFINITY, f32::min),
+            SampleMatch::Centroid => measure.distance(&self.centroid, other),
         }
+    }
 
-        for coeff in &self.coefficients.g {
-            writer.write_f32_le(*coeff).await?;
+    /// Like `distance_to`, but ignoring samples identical to `other`, as if
+    /// it had never been added. `None` if no other sample remains.
+    pub fn distance_excluding(
+        &self,
+        other: &Coefficients,
+        measure: &Measure,
+        mode: SampleMatch,
+    ) -> Option<f32> {
+        let samples = self
+            .samples
+            .iter()
+            .filter(|sample| *sample != other)
+            .collect::<Vec<_>>();
+
+        if samples.is_empty() {
+            return None;
         }
 
-        for coeff in &self.coefficients.b {
-            writer.write_f32_le(*coeff).await?;
+        Some(match mode {
+            SampleMatch::Nearest => samples
+                .iter()
+                .map(|sample| measure.distance(sample, other))
+                .fold(f32::INFINITY, f32::min),
+            SampleMatch::Centroid => {
+                measure.distance(&Coefficients::mean(samples.into_iter()), other)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(num_values: usize) -> Coefficients {
+        Coefficients((0..num_values).map(|i| i as f32 * 0.5).collect())
+    }
+
+    /// Writes a level with the given name and reads it back.
+    fn round_trip(name: &str) -> Level {
+        let level = Level::new(name.to_owned(), LevelDifficulty::Hard, sample(3));
+        let mut buf = vec![];
+        level.write(&mut buf).unwrap();
+
+        let mut cursor = Cursor::new(buf.as_slice());
+        let (read, mangled) = Level::read(&mut cursor, DATABASE_VERSION, 3).unwrap();
+        assert!(!mangled);
+        assert_eq!(cursor.position(), buf.len() as u64);
+        read
+    }
+
+    #[test]
+    fn round_trips_unicode_names() {
+        for name in [
+            "中文关卡",
+            "ブラッドバス",
+            "블러드배스",
+            "🔥 bloodbath 💀",
+            "👨‍👩‍👧 family",
+            "cafe\u{301}",
+            "Z\u{351}\u{36b}a\u{34b}l\u{325}g\u{33e}o",
+            "Ünïcödé Läyöut",
+        ] {
+            let level = round_trip(name);
+            assert_eq!(level.name, name);
+            assert_eq!(level.difficulty, LevelDifficulty::Hard);
+            assert_eq!(level.samples, vec![sample(3)]);
         }
+    }
 
-        Ok(())
+    #[test]
+    fn round_trips_long_names() {
+        for name in ["a".repeat(300), "中".repeat(200), "🔥".repeat(1000)] {
+            assert!(name.len() > 255);
+            assert_eq!(round_trip(&name).name, name);
+        }
+    }
+
+    #[test]
+    fn rejects_names_too_long_for_the_length_prefix() {
+        let name = "a".repeat(u16::MAX as usize + 1);
+        let level = Level::new(name, LevelDifficulty::Easy, sample(3));
+        assert!(level.write(&mut vec![]).is_err());
     }
 
-    pub fn euclidean_distance_to(&self, &other: &Coefficients) -> f32 {
-        let r = euclidean_distance(&self.coefficients.r, &other.r);
-        let g = euclidean_distance(&self.coefficients.g, &other.g);
-        let b = euclidean_distance(&self.coefficients.b, &other.b);
+    #[test]
+    fn round_trips_unicode_names_through_a_database() {
+        let fingerprint = FingerprintKind::Dct;
+        let long = "é".repeat(200);
+        let names = ["中文关卡", "🔥 bloodbath 💀", "cafe\u{301}", long.as_str()];
+        let levels = LevelSet {
+            fingerprint,
+            levels: names
+                .iter()
+                .map(|name| {
+                    let level = Level::new(
+                        name.to_string(),
+                        LevelDifficulty::Medium,
+                        sample(fingerprint.num_values()),
+                    );
+                    (name.to_string(), level)
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let database = Database::parse(&Database::serialize(&levels).unwrap()).unwrap();
+        assert!(database.mangled.is_empty());
+
+        let mut read = database
+            .levels
+            .iter()
+            .map(|level| level.name.as_str())
+            .collect::<Vec<_>>();
+        let mut expected = names.to_vec();
+        read.sort();
+        expected.sort();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    fn resolves_names_that_normalise_the_same() {
+        let mut levels = LevelSet::default();
+        let level = |name: &str| Level::new(name.to_owned(), LevelDifficulty::Easy, sample(3));
+        levels.insert("blood bath".to_owned(), level("blood bath"));
+        assert_eq!(levels.resolve("Blood  Bath."), Some("blood bath"));
+
+        // levels added and removed after the first lookup are kept track of
+        levels.insert("sonic wave".to_owned(), level("sonic wave"));
+        levels.insert("Blood Bath".to_owned(), level("Blood Bath"));
+        assert_eq!(levels.resolve("SONIC WAVE!"), Some("sonic wave"));
+        levels.remove("sonic wave");
+        assert_eq!(levels.resolve("sonic wave"), None);
+
+        // another level normalising the same takes over a removed one
+        let first = levels.resolve("BLOOD BATH").unwrap().to_owned();
+        levels.remove(&first);
+        assert!(levels
+            .resolve("blood bath!")
+            .is_some_and(|name| name != first));
+
+        levels.add_alias("bb", "blood bath").unwrap();
+        assert!(levels.add_alias("Sonic Wave", "bb").is_ok());
+        assert!(levels.add_alias("blood bath", "bb").is_err());
+    }
+
+    #[test]
+    fn renames_only_onto_free_names() {
+        let mut levels = LevelSet::default();
+        let level = |name: &str| Level::new(name.to_owned(), LevelDifficulty::Easy, sample(3));
+        levels.insert("blood bath".to_owned(), level("blood bath"));
+        levels.insert("sonic wave".to_owned(), level("sonic wave"));
+        levels.add_alias("sw", "sonic wave").unwrap();
+
+        // another level, one normalising the same or an alias of one
+        for to in ["sonic wave", "Sonic Wave!", "sw"] {
+            assert!(levels.rename("blood bath", to).is_err());
+            assert_eq!(levels.get("sonic wave").unwrap().samples.len(), 1);
+        }
+        assert!(levels.contains_key("blood bath"));
+
+        // the level's own name respelled, or its own alias
+        levels.rename("sonic wave", "Sonic Wave").unwrap();
+        assert_eq!(levels.resolve("sw"), Some("Sonic Wave"));
+        levels.rename("Sonic Wave", "sw").unwrap();
+        assert_eq!(levels.resolve("sw"), Some("sw"));
+        assert!(levels.aliases.is_empty());
+        assert_eq!(levels.len(), 2);
+    }
+
+    /// A one-level database in the current format.
+    fn database() -> Vec<u8> {
+        let fingerprint = FingerprintKind::Dct;
+        let level = Level::new(
+            "bloodbath".to_owned(),
+            LevelDifficulty::Hard,
+            sample(fingerprint.num_values()),
+        );
+        let levels = LevelSet {
+            fingerprint,
+            levels: [(level.name.to_owned(), level)].into_iter().collect(),
+            ..Default::default()
+        };
+        Database::serialize(&levels).unwrap()
+    }
+
+    /// Replaces a database's checksum with that of its (edited) body.
+    fn reseal(data: &mut [u8]) {
+        let body = data.len() - 4;
+        let checksum = crc32fast::hash(&data[..body]);
+        data[body..].copy_from_slice(&checksum.to_le_bytes());
+    }
+
+    #[test]
+    fn damaged_databases_are_corrupt() {
+        let data = database();
+        assert!(Database::parse(&data).is_ok());
+
+        let mut flipped = data.clone();
+        flipped[20] ^= 1;
+        let truncated = &data[..data.len() - 10];
+        for data in [flipped.as_slice(), truncated] {
+            assert!(matches!(
+                Database::parse(data),
+                Err(DatabaseError::Corrupt(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn intact_databases_from_other_builds_are_incompatible() {
+        let mut newer = database();
+        newer[4..6].copy_from_slice(&(DATABASE_VERSION + 1).to_le_bytes());
+        reseal(&mut newer);
+        assert!(matches!(
+            Database::parse(&newer),
+            Err(DatabaseError::Incompatible(_))
+        ));
+
+        // the header's IMAGE_DIM follows the magic, version and fingerprint
+        let mut resized = database();
+        resized[7..11].copy_from_slice(&(IMAGE_DIM as u32 * 2).to_le_bytes());
+        reseal(&mut resized);
+        assert!(matches!(
+            Database::parse(&resized),
+            Err(DatabaseError::Incompatible(_))
+        ));
+
+        // the block size follows IMAGE_DIM and the number of values
+        let mut reblocked = database();
+        reblocked[15..19].copy_from_slice(&(dct_block_size() as u32 + 1).to_le_bytes());
+        reseal(&mut reblocked);
+        assert!(matches!(
+            Database::parse(&reblocked),
+            Err(DatabaseError::Incompatible(_))
+        ));
+
+        // the records are still readable, just not comparable
+        let database = Database::parse_records(&resized).unwrap();
+        assert_eq!(database.levels[0].name, "bloodbath");
+    }
+
+    #[test]
+    fn flags_names_mangled_by_the_legacy_writer() {
+        let names = ["中文", "bloodbath", "🔥 fire"];
+
+        // a bare count, then each record as the legacy writer stored it: the
+        // UTF-8 length, one byte per char, the difficulty and the coefficients
+        let mut data = vec![];
+        data.write_u64::<LE>(names.len() as u64).unwrap();
+        for name in names {
+            data.write_u8(name.len() as u8).unwrap();
+            data.extend(name.chars().map(|c| c as u8));
+            data.write_u8(1).unwrap();
+            for _ in 0..3 * NUM_COEFFICIENTS {
+                data.write_f32::<LE>(1f32).unwrap();
+            }
+        }
 
-        // TODO: is average the best way to do this?
-        (r + g + b) / 3f32
+        let database = Database::parse(&data).unwrap();
+        assert_eq!(database.version(), 0);
+        assert_eq!(
+            database
+                .levels
+                .iter()
+                .map(|level| level.name.as_str())
+                .collect::<Vec<_>>(),
+            names.map(mangle_name)
+        );
+        assert_eq!(
+            database.mangled,
+            vec![mangle_name("中文"), mangle_name("🔥 fire")]
+        );
     }
 }
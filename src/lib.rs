@@ -0,0 +1,4 @@
+//! The parts of sparkyscrape that don't depend on the rest of it, in a library
+//! so the benchmarks can use them too.
+
+pub mod kernel;
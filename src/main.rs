@@ -1,24 +1,46 @@
+mod augment;
+mod bench;
+mod confidence;
+mod corpus;
+mod dedupe;
+mod download;
+mod eval;
+mod events;
+mod game;
 mod handler;
+mod index;
+mod journal;
 mod level;
+mod round;
+mod search;
+mod sparky;
+mod storage;
+mod transfer;
+mod tune;
 mod web;
 
-use std::{
-    collections::{hash_map::Entry, HashMap},
-    process,
-    sync::Arc,
-    time::Duration,
-};
+use std::{collections::HashMap, path::Path, process, sync::Arc, time::Duration};
 
 use clap::{Parser, Subcommand};
 use colored::Colorize;
-use handler::{save_levels, ChannelStateData, Handler, LevelDatabaseData, RawHandler};
+use download::{DownloadConfig, Downloader};
+use events::{ReplayEvents, SerenityEvents};
+use game::{GameContext, LevelDatabase};
+use handler::{Handler, RawHandler};
+use journal::Journal;
 use lazy_static::lazy_static;
-use level::read_levels;
+use level::{mangle_name, normalize_name, save_weights, unix_time};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use serenity::{all::ChannelId, prelude::TypeMapKey, Client};
+use serenity::{all::ChannelId, Client};
+use sparkyscrape::kernel;
 use tokio::sync::{mpsc, RwLock};
 
-use crate::level::LevelDifficulty;
+use crate::{
+    augment::Augmentation,
+    level::{FingerprintKind, Level, LevelDifficulty, LevelSet, SampleMatch, Weights},
+    storage::{BinStorage, MemoryStorage, SqliteStorage, Storage},
+};
 
 lazy_static! {
     static ref CONFIG: Config =
@@ -37,11 +59,58 @@ pub struct Config {
     pub bot_id: String,
     pub channels: Vec<String>,
     pub unprotected_ip: Option<String>,
+    /// how levels with several samples are matched against a guess
+    #[serde(default)]
+    pub sample_match: SampleMatch,
+    /// the fingerprint used for databases that don't exist yet
+    #[serde(default)]
+    pub fingerprint: FingerprintKind,
+    /// the side length of the block of 2-D DCT coefficients the `dct`
+    /// fingerprint keeps per channel, 4 if unset; databases built with
+    /// another need a `rebuild`
+    #[serde(default)]
+    pub dct_block_size: Option<usize>,
+    /// how many ranked guesses to keep for each round
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// compare every guess against every level instead of using an index
+    #[serde(default)]
+    pub exact_search: bool,
+    /// also search the other difficulties' databases, reporting when one of
+    /// them has a closer match
+    #[serde(default)]
+    pub search_all_difficulties: bool,
+    /// distance weights for difficulties without tuned ones
+    #[serde(default)]
+    pub weights: Option<Weights>,
+    /// append every game event to this JSONL file, keeping the screenshots in
+    /// `levels/rounds/`, so the games can be played again with `simulate`
+    #[serde(default)]
+    pub event_log: Option<String>,
+    /// append what happens in every round to this JSONL file, for `replay`
+    #[serde(default)]
+    pub journal: Option<String>,
+    /// the size in bytes past which the journal is rotated
+    #[serde(default = "default_journal_max_bytes")]
+    pub journal_max_bytes: u64,
+    /// how many rotated journals to keep
+    #[serde(default = "default_journal_keep")]
+    pub journal_keep: usize,
+    /// timeouts, retries and limits for downloading screenshots
+    #[serde(default)]
+    pub download: DownloadConfig,
+}
+
+fn default_top_k() -> usize {
+    5
+}
+
+fn default_journal_max_bytes() -> u64 {
+    16 * 1024 * 1024
 }
 
-pub struct WebMessageTxData;
-impl TypeMapKey for WebMessageTxData {
-    type Value = Arc<mpsc::UnboundedSender<web::WebMessage>>;
+fn default_journal_keep() -> usize {
+    5
 }
 
 #[derive(Parser)]
@@ -50,6 +119,15 @@ struct Cli {
     #[arg(long)]
     skip_ip_check: bool,
 
+    /// keep levels, aliases and games in this SQLite database instead of the
+    /// `.bin` files
+    #[arg(long, global = true)]
+    sqlite: Option<String>,
+
+    /// the DCT block size to use instead of the configured one
+    #[arg(long, global = true)]
+    dct_block_size: Option<usize>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -81,12 +159,377 @@ enum Command {
         #[arg(short, long, required = true)]
         level: String,
     },
+
+    /// let another spelling of a level's name count as that level
+    AddAlias {
+        /// the level's difficulty
+        #[arg(short, long, required = true)]
+        difficulty: String,
+
+        /// the level's name
+        #[arg(short, long, required = true)]
+        level: String,
+
+        /// the other spelling
+        #[arg(short, long, required = true)]
+        alias: String,
+    },
+
+    /// stop accepting another spelling of a level's name
+    RemoveAlias {
+        /// the level's difficulty
+        #[arg(short, long, required = true)]
+        difficulty: String,
+
+        /// the other spelling
+        #[arg(short, long, required = true)]
+        alias: String,
+    },
+
+    /// list the other accepted spellings of level names
+    Aliases {
+        /// the difficulty to list, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+    },
+
+    /// show a level's metadata, setting its ID or creator if given
+    Metadata {
+        /// the level's difficulty
+        #[arg(short, long, required = true)]
+        difficulty: String,
+
+        /// the level's name
+        #[arg(short, long, required = true)]
+        level: String,
+
+        /// the level's ID in Geometry Dash
+        #[arg(long)]
+        level_id: Option<u64>,
+
+        /// the level's creator
+        #[arg(long)]
+        creator: Option<String>,
+    },
+
+    /// recover level names mangled by older database versions
+    Repair {
+        /// the difficulty to repair, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// only report what would be repaired
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// recompute a database's samples from its saved images with another fingerprint
+    Refingerprint {
+        /// the difficulty to recompute, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// the fingerprint to recompute with
+        #[arg(short, long, default_value = "dct")]
+        fingerprint: String,
+
+        /// drop levels without a saved image instead of aborting
+        #[arg(long)]
+        drop_missing: bool,
+    },
+
+    /// rebuild a database from scratch using the saved level images
+    Rebuild {
+        /// the difficulty to rebuild, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// the fingerprint to use, or the existing database's if omitted
+        #[arg(short, long)]
+        fingerprint: Option<String>,
+
+        /// only report what would be rebuilt
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// measure recognition accuracy against a labelled image corpus
+    Eval {
+        /// the difficulty to evaluate, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// a held-out corpus laid out like `levels/` to match against the
+        /// database; without one, the saved images are matched leave-one-out
+        #[arg(short, long)]
+        corpus: Option<String>,
+
+        /// match against a database rebuilt from the saved images with this
+        /// fingerprint instead of the stored one (needs a held-out corpus)
+        #[arg(short, long, requires = "corpus")]
+        fingerprint: Option<String>,
+
+        /// how levels with several samples are matched
+        #[arg(short, long, default_value = "nearest")]
+        sample_match: String,
+
+        /// match perturbed copies of the images (JPEG re-encodes, crops,
+        /// offsets, rescales, brightness/contrast shifts and overlays) against
+        /// the database instead, reporting how far each moves the fingerprint
+        #[arg(short, long)]
+        augment: bool,
+
+        /// also write the reports to this file as JSON
+        #[arg(long)]
+        json: Option<String>,
+    },
+
+    /// fit the distance weights to a labelled image corpus and save them next
+    /// to the database
+    Tune {
+        /// the difficulty to tune, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// a held-out corpus laid out like `levels/` to fit to; without one,
+        /// the saved images are matched leave-one-out
+        #[arg(short, long)]
+        corpus: Option<String>,
+
+        /// fit to perturbed copies of the images instead, like `eval --augment`
+        #[arg(short, long)]
+        augment: bool,
+
+        /// how levels with several samples are matched
+        #[arg(short, long, default_value = "nearest")]
+        sample_match: String,
+
+        /// fit to an evenly spread subset of at most this many images, since
+        /// every one is matched against the whole database many times
+        #[arg(short, long, default_value_t = 500)]
+        max_queries: usize,
+
+        /// only report the fit, without saving the weights
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// find levels saved under several names and merge them
+    Dedupe {
+        /// the difficulty to dedupe, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// the fingerprint distance under which levels are candidates, or half
+        /// the distance a guess counts as known within if omitted
+        #[arg(long)]
+        max_distance: Option<f32>,
+
+        /// the name edit distance under which levels are candidates
+        #[arg(long, default_value_t = 2)]
+        max_edits: usize,
+
+        /// how levels with several samples are matched
+        #[arg(short, long, default_value = "nearest")]
+        sample_match: String,
+
+        /// apply the merges in this JSON file instead of finding candidates
+        #[arg(long)]
+        decisions: Option<String>,
+
+        /// write the candidates to this JSON file as decisions to edit and
+        /// pass back with `--decisions`, instead of asking about each one
+        #[arg(long)]
+        export: Option<String>,
+    },
+
+    /// write databases to a JSON or CSV file
+    Export {
+        /// the difficulty to export, or every difficulty if omitted
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// the file to write
+        #[arg(short, long, required = true)]
+        output: String,
+
+        /// json or csv, or from the file's extension if omitted
+        #[arg(long)]
+        format: Option<String>,
+    },
+
+    /// add levels from a JSON or CSV file written by `export`
+    Import {
+        /// the file to read
+        #[arg(short, long, required = true)]
+        input: String,
+
+        /// only import this difficulty's levels
+        #[arg(short, long)]
+        difficulty: Option<String>,
+
+        /// json or csv, or from the file's extension if omitted
+        #[arg(long)]
+        format: Option<String>,
+
+        /// what to do with a level whose name is taken: skip, overwrite or
+        /// keep-both (under a new name)
+        #[arg(long, default_value = "skip")]
+        on_conflict: String,
+
+        /// only report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// copy every database, its aliases and the games played between the
+    /// `.bin` files and a SQLite database
+    Convert {
+        /// the storage to copy to: sqlite or bin
+        #[arg(long, required = true)]
+        to: String,
+
+        /// the SQLite database to copy to or from
+        #[arg(long, default_value = "levels.db")]
+        database: String,
+    },
+
+    /// play the game over a recorded event log instead of Discord, leaving the
+    /// databases and images as they are unless told to learn
+    Simulate {
+        /// the JSONL event log to play
+        #[arg(short, long, required = true)]
+        log: String,
+
+        /// where the rounds' screenshots are
+        #[arg(short, long, default_value = events::ROUND_IMAGES)]
+        images: String,
+
+        /// journal the played rounds to this file, e.g. to `replay` them
+        #[arg(short, long)]
+        journal: Option<String>,
+
+        /// learn from the rounds like a live game would, saving to the
+        /// databases and images
+        #[arg(long)]
+        learn: bool,
+    },
+
+    /// match the rounds in the journal against the database as it is now,
+    /// with the configured matcher, comparing the guesses with the ones made at
+    /// the time
+    Replay {
+        /// the journal, read along with its rotated files
+        #[arg(short, long, default_value = "journal.jsonl")]
+        journal: String,
+
+        /// list every round, not just the ones whose guess changed
+        #[arg(short, long)]
+        all: bool,
+
+        /// also write the report to this file as JSON
+        #[arg(long)]
+        json: Option<String>,
+    },
+
+    /// compare the search kernel and index against a full scan of the database
+    Bench {
+        /// the difficulty to benchmark
+        #[arg(short, long, required = true)]
+        difficulty: String,
+
+        /// pad the database with made-up levels up to this many
+        #[arg(short, long)]
+        levels: Option<usize>,
+
+        /// how many lookups to time
+        #[arg(short, long, default_value_t = 1000)]
+        queries: usize,
+
+        /// how many guesses each lookup returns
+        #[arg(short, long, default_value_t = 5)]
+        k: usize,
+
+        /// how far queries stray from their sample, in standard deviations
+        #[arg(short, long, default_value_t = 0.05)]
+        noise: f32,
+
+        /// how levels with several samples are matched
+        #[arg(short, long, default_value = "nearest")]
+        sample_match: String,
+    },
+}
+
+/// Reads every difficulty's levels, readies them for searching, and fills in
+/// the configured fingerprint and weights where they're missing.
+async fn load_database(storage: &dyn Storage) -> LevelDatabase {
+    let mut map = HashMap::new();
+
+    for difficulty in LevelDifficulty::ALL {
+        let mut levels = storage.read_levels(difficulty).await;
+
+        // new databases use the configured fingerprint
+        if levels.is_empty() {
+            levels.fingerprint = CONFIG.fingerprint;
+        }
+
+        // tuned weights win over configured ones
+        if levels.weights.is_none() {
+            match &CONFIG.weights {
+                Some(weights) if weights.fits(levels.fingerprint) => {
+                    levels.weights = Some(Arc::new(weights.clone()));
+                }
+                Some(_) => println!(
+                    "{} the configured weights don't fit the {} {} fingerprint",
+                    "warning!".yellow().bold(),
+                    difficulty,
+                    levels.fingerprint
+                ),
+                None => (),
+            }
+        }
+
+        levels.threshold = confidence::learn_threshold(&levels, CONFIG.sample_match);
+        if let Some(threshold) = levels.threshold {
+            println!(
+                "{} levels are known within dist {:.2}",
+                difficulty, threshold
+            );
+        }
+
+        levels.build_kernel(CONFIG.sample_match);
+        if !CONFIG.exact_search {
+            levels.build_index(CONFIG.sample_match);
+        }
+
+        map.insert(difficulty, RwLock::new(levels));
+    }
+
+    Arc::new(map)
 }
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
 
+    // the block size can't change once something was fingerprinted
+    let dct_block_size = cli.dct_block_size.or_else(|| {
+        Path::new("config.json")
+            .exists()
+            .then(|| CONFIG.dct_block_size)?
+    });
+    if let Some(size) = dct_block_size {
+        if let Err(err) = level::set_dct_block_size(size) {
+            println!("{} {:#}", "error!".red().bold(), err);
+            process::exit(1);
+        }
+    }
+
+    let storage: Arc<dyn Storage> = match &cli.sqlite {
+        Some(path) => Arc::new(SqliteStorage::open(path).unwrap()),
+        None => Arc::new(BinStorage),
+    };
+
     #[allow(clippy::single_match)]
     match cli.command {
         Some(Command::Rename {
@@ -95,35 +538,33 @@ async fn main() {
             to,
         }) => {
             let difficulty = difficulty.parse().unwrap();
-            let mut levels = read_levels(difficulty).await;
+            let mut levels = storage.read_levels(difficulty).await;
 
-            if let Entry::Occupied(mut entry) = levels.entry(from.to_owned()) {
-                entry.get_mut().name = to.to_owned();
-                save_levels(difficulty, levels.len(), levels.values())
-                    .await
-                    .unwrap();
-                println!(
-                    "renamed {} to {}",
-                    difficulty.colorize(from.as_str()),
-                    difficulty.colorize(to.as_str())
-                );
-            } else {
+            if !levels.contains_key(&from) {
                 println!("could not find a level with the name {}", from.red());
                 process::exit(1);
             }
+            if let Err(err) = levels.rename(&from, &to) {
+                println!("{} {:#}", "error!".red().bold(), err);
+                process::exit(1);
+            }
+            storage.save_levels(difficulty, &levels).await.unwrap();
+            println!(
+                "renamed {} to {}",
+                difficulty.colorize(from.as_str()),
+                difficulty.colorize(to.as_str())
+            );
 
             process::exit(0);
         }
 
         Some(Command::Remove { difficulty, level }) => {
             let difficulty = difficulty.parse().unwrap();
-            let mut levels = read_levels(difficulty).await;
+            let mut levels = storage.read_levels(difficulty).await;
 
-            if let Entry::Occupied(entry) = levels.entry(level.to_owned()) {
-                entry.remove();
-                save_levels(difficulty, levels.len(), levels.values())
-                    .await
-                    .unwrap();
+            if levels.remove(&level).is_some() {
+                levels.retarget_aliases(&level, None);
+                storage.save_levels(difficulty, &levels).await.unwrap();
                 println!("removed {}", difficulty.colorize(level.as_str()),);
             } else {
                 println!("could not find a level with the name {}", level.red());
@@ -133,6 +574,1000 @@ async fn main() {
             process::exit(0);
         }
 
+        Some(Command::AddAlias {
+            difficulty,
+            level,
+            alias,
+        }) => {
+            let difficulty = difficulty.parse().unwrap();
+            let mut levels = storage.read_levels(difficulty).await;
+
+            match levels.add_alias(&alias, &level) {
+                Ok(level) => {
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                    println!(
+                        "{} now means {}",
+                        normalize_name(&alias),
+                        difficulty.colorize(level.as_str())
+                    );
+                }
+                Err(err) => {
+                    println!("could not add {}: {:#}", alias.red(), err);
+                    process::exit(1);
+                }
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::RemoveAlias { difficulty, alias }) => {
+            let difficulty = difficulty.parse().unwrap();
+            let mut levels = storage.read_levels(difficulty).await;
+
+            if let Some(level) = levels.remove_alias(&alias) {
+                storage.save_levels(difficulty, &levels).await.unwrap();
+                println!(
+                    "{} no longer means {}",
+                    normalize_name(&alias),
+                    difficulty.colorize(level.as_str())
+                );
+            } else {
+                println!("could not find an alias {}", alias.red());
+                process::exit(1);
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Aliases { difficulty }) => {
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            for difficulty in difficulties {
+                let levels = storage.read_levels(difficulty).await;
+                for (alias, level) in &levels.aliases {
+                    println!(
+                        "{} -> {}{}",
+                        alias,
+                        difficulty.colorize(level.as_str()),
+                        if levels.contains_key(level) {
+                            "".normal()
+                        } else {
+                            " (missing)".red()
+                        }
+                    );
+                }
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Metadata {
+            difficulty,
+            level,
+            level_id,
+            creator,
+        }) => {
+            let difficulty: LevelDifficulty = difficulty.parse().unwrap();
+            let mut levels = storage.read_levels(difficulty).await;
+
+            let Some(name) = levels.resolve(&level).map(str::to_owned) else {
+                println!("could not find a level with the name {}", level.red());
+                process::exit(1);
+            };
+
+            let metadata = &mut levels.get_mut(&name).unwrap().metadata;
+            let changed = level_id.is_some() || creator.is_some();
+            if level_id.is_some() {
+                metadata.level_id = level_id;
+            }
+            if creator.is_some() {
+                metadata.creator = creator;
+            }
+
+            let now = unix_time();
+            let date = |time: Option<u64>| match time.map(|time| now.saturating_sub(time)) {
+                Some(secs) if secs < 3600 => format!("{} minutes ago", secs / 60),
+                Some(secs) if secs < 86400 => format!("{} hours ago", secs / 3600),
+                Some(secs) => format!("{} days ago", secs / 86400),
+                None => "never".to_string(),
+            };
+            println!("{}", difficulty.colorize(name.as_str()).bold());
+            println!(
+                "  level ID    {}",
+                metadata
+                    .level_id
+                    .map_or("unknown".to_string(), |id| id.to_string())
+            );
+            println!(
+                "  creator     {}",
+                metadata.creator.as_deref().unwrap_or("unknown")
+            );
+            println!("  first seen  {}", date(metadata.first_seen));
+            println!("  last seen   {}", date(metadata.last_seen));
+            println!(
+                "  guessed     {}/{} rounds",
+                metadata.times_guessed, metadata.times_seen
+            );
+
+            if changed {
+                storage.save_levels(difficulty, &levels).await.unwrap();
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Repair {
+            difficulty,
+            dry_run,
+        }) => {
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            for difficulty in difficulties {
+                // only the legacy records show which names were mangled, so
+                // they're read before a migration replaces them
+                let mangled = storage::mangled_names(difficulty).await;
+                if mangled.is_empty() {
+                    println!("no {} level names were mangled", difficulty);
+                    continue;
+                }
+
+                let mut levels = if dry_run {
+                    storage.peek_levels(difficulty).await
+                } else {
+                    storage.read_levels(difficulty).await
+                };
+
+                // winning screenshots are saved under the real (unmangled) name
+                let images = std::fs::read_dir(format!("levels/{}", difficulty.directory()))
+                    .unwrap()
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| Some(entry.path().file_stem()?.to_str()?.to_owned()))
+                    .collect::<Vec<_>>();
+
+                let mut renames = vec![];
+                for name in &mangled {
+                    // already repaired, or its name happens to survive mangling
+                    if !levels.contains_key(name) || images.contains(name) {
+                        continue;
+                    }
+
+                    match images.iter().find(|image| &mangle_name(image) == name) {
+                        Some(image) => renames.push((name.to_owned(), image.to_owned())),
+                        None => println!(
+                            "could not recover {}, no matching image",
+                            difficulty.colorize(name.as_str()).red()
+                        ),
+                    }
+                }
+
+                let mut renamed = 0;
+                let mut dropped = 0;
+                for (from, to) in &renames {
+                    let mut level = levels.remove(from).unwrap();
+                    levels.retarget_aliases(from, Some(to.as_str()));
+                    if levels.contains_key(to) {
+                        println!(
+                            "{} {}, {} already exists",
+                            if dry_run { "would drop" } else { "dropped" },
+                            difficulty.colorize(from.as_str()),
+                            difficulty.colorize(to.as_str())
+                        );
+                        dropped += 1;
+                        continue;
+                    }
+
+                    println!(
+                        "{} {} to {}",
+                        if dry_run { "would repair" } else { "repaired" },
+                        difficulty.colorize(from.as_str()),
+                        difficulty.colorize(to.as_str())
+                    );
+                    level.name = to.to_owned();
+                    levels.insert(to.to_owned(), level);
+                    renamed += 1;
+                }
+
+                if !renames.is_empty() && !dry_run {
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                }
+
+                println!(
+                    "{} {} {} levels, {} {} mangled copies of existing levels",
+                    if dry_run { "would repair" } else { "repaired" },
+                    renamed,
+                    difficulty,
+                    if dry_run { "would drop" } else { "dropped" },
+                    dropped
+                );
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Refingerprint {
+            difficulty,
+            fingerprint,
+            drop_missing,
+        }) => {
+            let fingerprint: FingerprintKind = fingerprint.parse().unwrap();
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            for difficulty in difficulties {
+                let levels = storage.read_levels(difficulty).await;
+
+                // only the winning screenshot is saved, so each level ends up
+                // with a single sample
+                let recomputed = levels
+                    .par_iter()
+                    .map(|(name, level)| {
+                        let coefficients = std::fs::read(difficulty.image_path(name))
+                            .ok()
+                            .and_then(|data| fingerprint.compute(&data).ok());
+                        (name, level, coefficients)
+                    })
+                    .collect::<Vec<_>>();
+
+                let missing = recomputed
+                    .iter()
+                    .filter(|(_, _, coefficients)| coefficients.is_none())
+                    .map(|(name, _, _)| name.as_str())
+                    .collect::<Vec<_>>();
+
+                for name in &missing {
+                    println!("no readable image for {}", difficulty.colorize(*name).red());
+                }
+
+                if !missing.is_empty() && !drop_missing {
+                    println!(
+                        "{} {} {} levels have no readable image, pass --drop-missing to drop them",
+                        "error!".red().bold(),
+                        missing.len(),
+                        difficulty
+                    );
+                    process::exit(1);
+                }
+
+                let new_levels = LevelSet {
+                    fingerprint,
+                    levels: recomputed
+                        .into_iter()
+                        .filter_map(|(name, level, coefficients)| {
+                            let mut new_level =
+                                Level::new(name.to_owned(), level.difficulty, coefficients?);
+                            new_level.metadata = level.metadata.clone();
+                            Some((name.to_owned(), new_level))
+                        })
+                        .collect(),
+                    aliases: levels.aliases.clone(),
+                    ..Default::default()
+                };
+
+                storage.save_levels(difficulty, &new_levels).await.unwrap();
+                println!(
+                    "recomputed {} {} levels with {} (was {}), dropped {}",
+                    new_levels.len(),
+                    difficulty,
+                    fingerprint,
+                    levels.fingerprint,
+                    missing.len()
+                );
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Rebuild {
+            difficulty,
+            fingerprint,
+            dry_run,
+        }) => {
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            for difficulty in difficulties {
+                // the old database may be one this build can't search, which
+                // is when a rebuild is needed, so only its names, metadata and
+                // aliases are kept, and only if it can be read at all
+                let existing = match storage.read_records(difficulty).await {
+                    Ok(existing) => existing,
+                    Err(err) => {
+                        println!(
+                            "{} could not read the old {} database, rebuilding without its metadata and aliases: {:#}",
+                            "warning!".yellow().bold(),
+                            difficulty,
+                            err
+                        );
+                        LevelSet::default()
+                    }
+                };
+                let fingerprint: FingerprintKind = match &fingerprint {
+                    Some(fingerprint) => fingerprint.parse().unwrap(),
+                    None => existing.fingerprint,
+                };
+
+                let images = corpus::list_images(Path::new("levels"), difficulty).unwrap();
+                let computed = corpus::fingerprint_images(&images, fingerprint);
+
+                let mut levels = LevelSet {
+                    fingerprint,
+                    levels: HashMap::new(),
+                    aliases: existing.aliases.clone(),
+                    ..Default::default()
+                };
+                let mut seen_coefficients = HashMap::new();
+                let mut unreadable = 0;
+                let mut shadowed = 0;
+
+                for (image, coefficients) in images.iter().zip(computed) {
+                    let coefficients = match coefficients {
+                        Ok(coefficients) => coefficients,
+                        Err(err) => {
+                            println!("could not read {}: {:#}", image.path.display(), err);
+                            unreadable += 1;
+                            continue;
+                        }
+                    };
+
+                    // the same screenshot saved under two names
+                    let bits = coefficients
+                        .0
+                        .iter()
+                        .map(|v| v.to_bits())
+                        .collect::<Vec<_>>();
+                    if let Some(other) = seen_coefficients
+                        .insert(bits, image.name.to_owned())
+                        .filter(|other| other != &image.name)
+                    {
+                        println!(
+                            "{} and {} have identical images",
+                            difficulty.colorize(other.as_str()),
+                            difficulty.colorize(image.name.as_str())
+                        );
+                    }
+
+                    // names are lowercased, so this is the same name with
+                    // another extension or in another case
+                    if levels.contains_key(&image.name) {
+                        println!(
+                            "skipping {}, {} already has an image under the same name",
+                            image.path.display(),
+                            difficulty.colorize(image.name.as_str())
+                        );
+                        shadowed += 1;
+                        continue;
+                    }
+
+                    let mut level =
+                        Level::new(image.name.to_owned(), image.difficulty, coefficients);
+                    if let Some(existing) = existing.get(&image.name) {
+                        level.metadata = existing.metadata.clone();
+                    }
+                    levels.insert(image.name.to_owned(), level);
+                }
+
+                let dropped = existing
+                    .keys()
+                    .filter(|name| !levels.contains_key(*name))
+                    .count();
+
+                println!(
+                    "{} {} {} levels with {} ({} unreadable, {} with the name of another image, {} in the old database without an image)",
+                    if dry_run { "would rebuild" } else { "rebuilt" },
+                    levels.len(),
+                    difficulty,
+                    fingerprint,
+                    unreadable,
+                    shadowed,
+                    dropped
+                );
+
+                if !dry_run {
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                }
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Eval {
+            difficulty,
+            corpus: corpus_root,
+            fingerprint,
+            sample_match,
+            augment,
+            json,
+        }) => {
+            let sample_match: SampleMatch = sample_match.parse().unwrap();
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            let mut reports = vec![];
+            for difficulty in difficulties {
+                let mut levels = storage.read_levels(difficulty).await;
+
+                // try out a fingerprint without touching the stored database
+                if let Some(fingerprint) = &fingerprint {
+                    let fingerprint: FingerprintKind = fingerprint.parse().unwrap();
+                    let images = corpus::list_images(Path::new("levels"), difficulty).unwrap();
+                    let computed = corpus::fingerprint_images(&images, fingerprint);
+
+                    levels = LevelSet {
+                        fingerprint,
+                        levels: images
+                            .into_iter()
+                            .zip(computed)
+                            .filter_map(|(image, coefficients)| {
+                                let level =
+                                    Level::new(image.name, image.difficulty, coefficients.ok()?);
+                                Some((level.name.to_owned(), level))
+                            })
+                            .collect(),
+                        ..Default::default()
+                    };
+                }
+
+                let root = corpus_root.as_deref().unwrap_or("levels");
+                let images = corpus::list_images(Path::new(root), difficulty).unwrap();
+
+                if augment {
+                    println!("{} {} robustness", difficulty, levels.fingerprint);
+                    for report in augment::evaluate(
+                        difficulty,
+                        &levels,
+                        &images,
+                        &Augmentation::STANDARD,
+                        sample_match,
+                    ) {
+                        report.print(difficulty);
+                        reports.push(serde_json::to_value(report).unwrap());
+                    }
+                    continue;
+                }
+
+                let queries = eval::queries(images, levels.fingerprint);
+                let report = eval::evaluate(
+                    difficulty,
+                    &levels,
+                    &queries,
+                    sample_match,
+                    corpus_root.is_none(),
+                );
+                report.print(difficulty);
+                reports.push(serde_json::to_value(report).unwrap());
+            }
+
+            if let Some(json) = json {
+                std::fs::write(json, serde_json::to_string_pretty(&reports).unwrap()).unwrap();
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Tune {
+            difficulty,
+            corpus: corpus_root,
+            augment,
+            sample_match,
+            max_queries,
+            dry_run,
+        }) => {
+            let sample_match: SampleMatch = sample_match.parse().unwrap();
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            for difficulty in difficulties {
+                let mut levels = storage.read_levels(difficulty).await;
+                if levels.fingerprint.channel_size().is_none() {
+                    println!(
+                        "{} {} has no weights to tune",
+                        difficulty, levels.fingerprint
+                    );
+                    continue;
+                }
+
+                let root = corpus_root.as_deref().unwrap_or("levels");
+                let images = corpus::list_images(Path::new(root), difficulty).unwrap();
+                let queries = if augment {
+                    augment::queries(levels.fingerprint, &images, &Augmentation::STANDARD)
+                } else {
+                    eval::queries(images, levels.fingerprint)
+                };
+                let step = queries.len().div_ceil(max_queries.max(1)).max(1);
+                let queries = queries.into_iter().step_by(step).collect::<Vec<_>>();
+                let leave_one_out = corpus_root.is_none() && !augment;
+
+                println!(
+                    "tuning {} {} on {} queries",
+                    difficulty,
+                    levels.fingerprint,
+                    queries.len()
+                );
+                let before =
+                    eval::evaluate(difficulty, &levels, &queries, sample_match, leave_one_out);
+                tune::tune(&mut levels, &queries, sample_match, leave_one_out).unwrap();
+                let after =
+                    eval::evaluate(difficulty, &levels, &queries, sample_match, leave_one_out);
+
+                let weights = levels.weights.as_deref().unwrap();
+                println!(
+                    "{} top-1 {:.1}% -> {}, MRR {:.3} -> {:.3}",
+                    difficulty,
+                    before.top1 * 100f32,
+                    format!("{:.1}%", after.top1 * 100f32).bold(),
+                    before.mean_reciprocal_rank,
+                    after.mean_reciprocal_rank
+                );
+                println!("  coefficient weights {:.3?}", weights.coefficients);
+                println!("  channel weights {:.3?}", weights.channels);
+                if let Some(threshold) = levels.threshold {
+                    println!("  threshold relearned as {:.3}", threshold);
+                }
+
+                if !dry_run {
+                    save_weights(difficulty, weights).unwrap();
+                    println!("saved to {}", difficulty.weights_filename());
+                }
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Dedupe {
+            difficulty,
+            max_distance,
+            max_edits,
+            sample_match,
+            decisions,
+            export,
+        }) => {
+            let sample_match: SampleMatch = sample_match.parse().unwrap();
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            if let Some(decisions) = decisions {
+                let decisions: Vec<dedupe::Decision> =
+                    serde_json::from_str(&std::fs::read_to_string(decisions).unwrap()).unwrap();
+
+                for difficulty in difficulties {
+                    let planned = decisions
+                        .iter()
+                        .filter(|decision| decision.difficulty.parse() == Ok(difficulty))
+                        .collect::<Vec<_>>();
+                    if planned.is_empty() {
+                        continue;
+                    }
+
+                    let mut levels = storage.read_levels(difficulty).await;
+                    let mut moves = dedupe::ImageMoves::default();
+                    for decision in planned {
+                        match dedupe::merge(
+                            difficulty,
+                            &mut levels,
+                            &decision.into,
+                            &decision.merge,
+                            &mut moves,
+                        ) {
+                            Ok(()) => println!(
+                                "merged {} into {}",
+                                decision.merge.join(", "),
+                                difficulty.colorize(decision.into.as_str())
+                            ),
+                            Err(err) => println!(
+                                "{} could not merge into {}: {:#}",
+                                "error!".red().bold(),
+                                decision.into,
+                                err
+                            ),
+                        }
+                    }
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                    moves.apply().unwrap();
+                }
+
+                process::exit(0);
+            }
+
+            let mut exported = vec![];
+            for difficulty in difficulties {
+                let mut levels = storage.read_levels(difficulty).await;
+                let mut moves = dedupe::ImageMoves::default();
+                let max_distance = max_distance
+                    .or_else(|| {
+                        confidence::learn_threshold(&levels, sample_match)
+                            .map(|threshold| threshold / 2f32)
+                    })
+                    .unwrap_or(0f32);
+
+                let clusters =
+                    dedupe::find_clusters(&levels, max_distance, max_edits, sample_match);
+                println!(
+                    "{} {} candidate duplicates (within dist {:.2} or {} edits)",
+                    clusters.len(),
+                    difficulty,
+                    max_distance,
+                    max_edits
+                );
+
+                let mut merged = 0;
+                for (i, cluster) in clusters.iter().enumerate() {
+                    println!("{} {}/{}", difficulty, i + 1, clusters.len());
+                    cluster.print(difficulty, &levels);
+
+                    let suggested = cluster.suggested(&levels).to_owned();
+                    if export.is_some() {
+                        exported.push(dedupe::Decision {
+                            difficulty: difficulty.directory().to_owned(),
+                            merge: cluster
+                                .names
+                                .iter()
+                                .filter(|name| **name != suggested)
+                                .cloned()
+                                .collect(),
+                            into: suggested,
+                        });
+                        continue;
+                    }
+
+                    let answer = loop {
+                        let answer = dedupe::prompt(&format!(
+                            "merge into which? (1-{}, s to skip, q to stop)",
+                            cluster.names.len()
+                        ));
+                        match answer.as_str() {
+                            "s" | "q" => break answer,
+                            _ => match answer.parse::<usize>() {
+                                Ok(n) if (1..=cluster.names.len()).contains(&n) => break answer,
+                                _ => (),
+                            },
+                        }
+                    };
+
+                    let into = match answer.as_str() {
+                        "s" => continue,
+                        "q" => break,
+                        n => &cluster.names[n.parse::<usize>().unwrap() - 1],
+                    };
+                    let others = cluster
+                        .names
+                        .iter()
+                        .filter(|name| *name != into)
+                        .cloned()
+                        .collect::<Vec<_>>();
+                    dedupe::merge(difficulty, &mut levels, into, &others, &mut moves).unwrap();
+                    println!(
+                        "merged {} into {}",
+                        others.join(", "),
+                        difficulty.colorize(into.as_str())
+                    );
+                    merged += 1;
+                }
+
+                if merged > 0 {
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                    moves.apply().unwrap();
+                }
+            }
+
+            if let Some(export) = export {
+                std::fs::write(&export, serde_json::to_string_pretty(&exported).unwrap()).unwrap();
+                println!("wrote {} decisions to {}", exported.len(), export);
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Export {
+            difficulty,
+            output,
+            format,
+        }) => {
+            let format = match format {
+                Some(format) => format.parse().unwrap(),
+                None => transfer::Format::from_path(&output).unwrap_or(transfer::Format::Json),
+            };
+            let difficulties = match difficulty {
+                Some(difficulty) => vec![difficulty.parse().unwrap()],
+                None => LevelDifficulty::ALL.to_vec(),
+            };
+
+            let mut databases = vec![];
+            for difficulty in difficulties {
+                let levels = storage.read_levels(difficulty).await;
+                println!("exporting {} {} levels", levels.len(), difficulty);
+                databases.push(transfer::export(difficulty, &levels));
+            }
+
+            transfer::write(&output, format, &databases).unwrap();
+            println!("wrote {}", output);
+
+            process::exit(0);
+        }
+
+        Some(Command::Import {
+            input,
+            difficulty,
+            format,
+            on_conflict,
+            dry_run,
+        }) => {
+            let format = match format {
+                Some(format) => format.parse().unwrap(),
+                None => transfer::Format::from_path(&input).unwrap_or(transfer::Format::Json),
+            };
+            let conflict: transfer::Conflict = on_conflict.parse().unwrap();
+            let only: Option<LevelDifficulty> = difficulty.map(|d| d.parse().unwrap());
+
+            let databases = match transfer::read(&input, format) {
+                Ok(databases) => databases,
+                Err(err) => {
+                    println!(
+                        "{} could not read {}: {:#}",
+                        "error!".red().bold(),
+                        input,
+                        err
+                    );
+                    process::exit(1);
+                }
+            };
+
+            for database in databases {
+                let Ok(difficulty) = database.difficulty.parse::<LevelDifficulty>() else {
+                    println!(
+                        "{} skipping unknown difficulty {}",
+                        "warning!".yellow().bold(),
+                        database.difficulty
+                    );
+                    continue;
+                };
+                if only.is_some_and(|only| only != difficulty) {
+                    continue;
+                }
+
+                let mut levels = if dry_run {
+                    storage.peek_levels(difficulty).await
+                } else {
+                    storage.read_levels(difficulty).await
+                };
+                let summary = match transfer::import(difficulty, &mut levels, database, conflict) {
+                    Ok(summary) => summary,
+                    Err(err) => {
+                        println!(
+                            "{} could not import {} levels: {:#}",
+                            "error!".red().bold(),
+                            difficulty,
+                            err
+                        );
+                        process::exit(1);
+                    }
+                };
+
+                for (from, to) in &summary.renamed {
+                    println!(
+                        "{} kept as {}",
+                        difficulty.colorize(from.as_str()),
+                        difficulty.colorize(to.as_str())
+                    );
+                }
+                println!(
+                    "{} {} {} levels ({} skipped, {} overwritten, {} kept under a new name)",
+                    if dry_run { "would import" } else { "imported" },
+                    summary.added,
+                    difficulty,
+                    summary.skipped,
+                    summary.overwritten,
+                    summary.renamed.len()
+                );
+
+                if !dry_run {
+                    storage.save_levels(difficulty, &levels).await.unwrap();
+                }
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Convert { to, database }) => {
+            // an empty database would overwrite the .bin files with nothing
+            if to == "bin" && !Path::new(&database).exists() {
+                println!("{} {} does not exist", "error!".red().bold(), database);
+                process::exit(1);
+            }
+
+            let sqlite: Arc<dyn Storage> = Arc::new(SqliteStorage::open(&database).unwrap());
+            let bin: Arc<dyn Storage> = Arc::new(BinStorage);
+            let (from, to) = match to.as_str() {
+                "sqlite" => (bin, sqlite),
+                "bin" => (sqlite, bin),
+                _ => {
+                    println!(
+                        "{} can only convert to sqlite or bin",
+                        "error!".red().bold()
+                    );
+                    process::exit(1);
+                }
+            };
+
+            for difficulty in LevelDifficulty::ALL {
+                let levels = from.read_levels(difficulty).await;
+                to.save_levels(difficulty, &levels).await.unwrap();
+                println!(
+                    "copied {} {} levels and {} aliases",
+                    levels.len(),
+                    difficulty,
+                    levels.aliases.len()
+                );
+            }
+
+            // games are only ever added, so copying them twice would
+            // duplicate them
+            let games = from.games().await.unwrap();
+            if to.games().await.unwrap().is_empty() {
+                for game in &games {
+                    to.record_game(game).await.unwrap();
+                }
+                println!("copied {} games", games.len());
+            } else if !games.is_empty() {
+                println!(
+                    "{} not copying {} games, the destination already has some",
+                    "warning!".yellow().bold(),
+                    games.len()
+                );
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Simulate {
+            log,
+            images,
+            journal,
+            learn,
+        }) => {
+            let source = match ReplayEvents::open(&log, &images) {
+                Ok(source) => source,
+                Err(err) => {
+                    println!(
+                        "{} could not read {}: {:#}",
+                        "error!".red().bold(),
+                        log,
+                        err
+                    );
+                    process::exit(1);
+                }
+            };
+            println!("playing {} events from {}", source.remaining(), log);
+
+            // nobody is watching, so web messages go nowhere
+            let (web_tx, mut web_rx) = mpsc::unbounded_channel();
+            tokio::spawn(async move { while web_rx.recv().await.is_some() {} });
+
+            let storage: Arc<dyn Storage> = if learn {
+                Arc::clone(&storage)
+            } else {
+                Arc::new(MemoryStorage::new(Arc::clone(&storage)))
+            };
+            let game = GameContext {
+                channels: Arc::new(RwLock::new(HashMap::new())),
+                database: load_database(storage.as_ref()).await,
+                storage,
+                downloader: Downloader::new(CONFIG.download.clone()).unwrap(),
+                web: web_tx,
+                journal: journal
+                    .map(|journal| Arc::new(Journal::open(&journal, u64::MAX, 0).unwrap())),
+                save_images: learn,
+                tasks: Default::default(),
+            };
+            let tasks = game.tasks.clone();
+            game::run(game, source).await;
+            // let saves and anything else still going finish
+            tasks.wait().await;
+
+            process::exit(0);
+        }
+
+        Some(Command::Replay { journal, all, json }) => {
+            let entries = match journal::read(&journal) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    println!(
+                        "{} could not read {}: {:#}",
+                        "error!".red().bold(),
+                        journal,
+                        err
+                    );
+                    process::exit(1);
+                }
+            };
+            let rounds = journal::rounds(entries);
+
+            // the database the bot would play with, without the locks
+            let database = Arc::try_unwrap(load_database(storage.as_ref()).await)
+                .ok()
+                .unwrap()
+                .into_iter()
+                .map(|(difficulty, levels)| (difficulty, levels.into_inner()))
+                .collect();
+
+            let report = journal::replay(&rounds, &database, CONFIG.top_k, CONFIG.sample_match);
+            report.print(all);
+
+            if let Some(json) = json {
+                std::fs::write(json, serde_json::to_string_pretty(&report).unwrap()).unwrap();
+            }
+
+            process::exit(0);
+        }
+
+        Some(Command::Bench {
+            difficulty,
+            levels: count,
+            queries,
+            k,
+            noise,
+            sample_match,
+        }) => {
+            let difficulty: LevelDifficulty = difficulty.parse().unwrap();
+            let sample_match: SampleMatch = sample_match.parse().unwrap();
+            let mut levels = storage.read_levels(difficulty).await;
+            let mut rng = bench::Rng::new(0x5eed);
+
+            if let Some(count) = count {
+                bench::pad(&mut levels, count, &mut rng);
+            }
+
+            let queries = bench::queries(&levels, queries, noise, &mut rng);
+            if queries.is_empty() {
+                println!("{} has no samples to benchmark with", difficulty);
+                process::exit(1);
+            }
+
+            let result = bench::run(&mut levels, &queries, k, sample_match);
+
+            let per_query = |total: Duration| total / queries.len() as u32;
+            println!(
+                "{} {} {} levels, {} queries, top-{}",
+                difficulty,
+                levels.fingerprint,
+                levels.len(),
+                queries.len(),
+                k
+            );
+            println!("  full scan  {:?}/query", per_query(result.scan));
+            for (label, timing) in [("kernel", &result.kernel), ("index", &result.index)] {
+                match timing {
+                    Some(timing) => println!(
+                        "  {:<10} {:?}/query ({:.1}x), built in {:?}, recall {:.2}%, same best guess {:.2}%",
+                        label,
+                        per_query(timing.search),
+                        result.scan.as_secs_f64() / timing.search.as_secs_f64(),
+                        timing.build,
+                        timing.recall * 100f32,
+                        timing.top1 * 100f32
+                    ),
+                    None => println!("  {:<10} not supported by this fingerprint", label),
+                }
+            }
+
+            process::exit(0);
+        }
+
         _ => (),
     }
 
@@ -164,20 +1599,7 @@ async fn main() {
     }
 
     // read levels in
-    let db = {
-        let mut map = HashMap::new();
-
-        for difficulty in &[
-            LevelDifficulty::Easy,
-            LevelDifficulty::Medium,
-            LevelDifficulty::Hard,
-            LevelDifficulty::Legendary,
-        ] {
-            map.insert(*difficulty, RwLock::new(read_levels(*difficulty).await));
-        }
-
-        Arc::new(map)
-    };
+    let db = load_database(storage.as_ref()).await;
 
     // start the web app
     let web_tx = web::init(web::Init {
@@ -189,21 +1611,31 @@ async fn main() {
     let mut cache_settings = serenity::cache::Settings::default();
     cache_settings.max_messages = 200;
 
+    let (events, events_tx) = SerenityEvents::new(CONFIG.event_log.as_deref()).unwrap();
+    let journal = CONFIG.journal.as_deref().map(|journal| {
+        Arc::new(Journal::open(journal, CONFIG.journal_max_bytes, CONFIG.journal_keep).unwrap())
+    });
+    tokio::spawn(game::run(
+        GameContext {
+            channels: Arc::new(RwLock::new(HashMap::new())),
+            database: db,
+            storage,
+            downloader: Downloader::new(CONFIG.download.clone()).unwrap(),
+            web: web_tx,
+            journal,
+            save_images: true,
+            tasks: Default::default(),
+        },
+        events,
+    ));
+
     let mut client = Client::builder(&CONFIG.token)
-        .event_handler(Handler)
+        .event_handler(Handler { events: events_tx })
         .raw_event_handler(RawHandler)
         .cache_settings(cache_settings)
         .await
         .expect("error creating client");
 
-    // initialize the cache
-    {
-        let mut data = client.data.write().await;
-        data.insert::<ChannelStateData>(Arc::new(RwLock::new(HashMap::new())));
-        data.insert::<WebMessageTxData>(Arc::new(web_tx));
-        data.insert::<LevelDatabaseData>(db);
-    }
-
     if let Err(why) = client.start().await {
         println!("Client error: {why:?}");
     }
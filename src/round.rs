@@ -0,0 +1,313 @@
+//! The life of a round in one channel, from Sparky posting the screenshot to
+//! someone winning, nobody winning, or us giving up on it.
+//!
+//! ```text
+//! Started → Downloading → Fingerprinted → Guessed ─┬→ Won
+//!    └───────────┴──────────────┴────────────┴─────┼→ TimedOut
+//!                │                                 └→ Abandoned
+//!                └→ Failed
+//! ```
+//!
+//! Sparky can end a round whenever it likes, so a round can be won, time out
+//! or be abandoned from any phase before it's over; nothing moves a finished
+//! round. A round fails if its screenshot can't be downloaded or read.
+
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Tells rounds apart, so work started for one round doesn't land in the
+/// next one in the same channel.
+pub type RoundId = u64;
+
+static NEXT_ROUND_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Sparky posted the screenshot
+    Started,
+    /// the screenshot is being loaded
+    Downloading,
+    /// we have the screenshot and its fingerprint
+    Fingerprinted,
+    /// we have our guesses
+    Guessed,
+    /// someone guessed the level
+    Won,
+    /// nobody guessed the level
+    TimedOut,
+    /// another round started before it ended
+    Abandoned,
+    /// the screenshot couldn't be downloaded or read
+    Failed,
+}
+
+impl Phase {
+    pub fn is_over(self) -> bool {
+        matches!(
+            self,
+            Self::Won | Self::TimedOut | Self::Abandoned | Self::Failed
+        )
+    }
+
+    /// Where a step leads from here, if it can be taken.
+    pub fn next(self, step: Step) -> Result<Phase, IllegalStep> {
+        use Phase::*;
+
+        Ok(match (self, step) {
+            (Started, Step::Download) => Downloading,
+            (Downloading, Step::Fingerprint) => Fingerprinted,
+            (Fingerprinted, Step::Guess) => Guessed,
+            (Downloading, Step::Fail) => Failed,
+            (phase, Step::Win) if !phase.is_over() => Won,
+            (phase, Step::TimeOut) if !phase.is_over() => TimedOut,
+            (phase, Step::Abandon) if !phase.is_over() => Abandoned,
+            _ => return Err(IllegalStep { phase: self, step }),
+        })
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Started => "started",
+                Self::Downloading => "downloading",
+                Self::Fingerprinted => "fingerprinted",
+                Self::Guessed => "guessed",
+                Self::Won => "won",
+                Self::TimedOut => "timed out",
+                Self::Abandoned => "abandoned",
+                Self::Failed => "failed",
+            }
+        )
+    }
+}
+
+/// What moves a round from one phase to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Download,
+    Fingerprint,
+    Guess,
+    Win,
+    TimeOut,
+    Abandon,
+    Fail,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Download => "download",
+                Self::Fingerprint => "fingerprint",
+                Self::Guess => "guess",
+                Self::Win => "win",
+                Self::TimeOut => "time out",
+                Self::Abandon => "abandon",
+                Self::Fail => "fail",
+            }
+        )
+    }
+}
+
+/// A step the round's phase doesn't allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalStep {
+    pub phase: Phase,
+    pub step: Step,
+}
+
+impl Display for IllegalStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "can't {} a round that's {}", self.step, self.phase)
+    }
+}
+
+impl std::error::Error for IllegalStep {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Round {
+    pub id: RoundId,
+    pub phase: Phase,
+}
+
+impl Round {
+    /// A new round, with an ID no other round has had.
+    pub fn start() -> Self {
+        Self {
+            id: NEXT_ROUND_ID.fetch_add(1, Ordering::Relaxed),
+            phase: Phase::Started,
+        }
+    }
+
+    /// Takes a step, leaving the round as it was if the step is illegal.
+    pub fn advance(&mut self, step: Step) -> Result<Phase, IllegalStep> {
+        self.phase = self.phase.next(step)?;
+        Ok(self.phase)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.phase.is_over()
+    }
+}
+
+impl Display for Round {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "round {}", self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHASES: [Phase; 8] = [
+        Phase::Started,
+        Phase::Downloading,
+        Phase::Fingerprinted,
+        Phase::Guessed,
+        Phase::Won,
+        Phase::TimedOut,
+        Phase::Abandoned,
+        Phase::Failed,
+    ];
+
+    const STEPS: [Step; 7] = [
+        Step::Download,
+        Step::Fingerprint,
+        Step::Guess,
+        Step::Win,
+        Step::TimeOut,
+        Step::Abandon,
+        Step::Fail,
+    ];
+
+    /// A round that has got as far as `phase`.
+    fn round_at(phase: Phase) -> Round {
+        let mut round = Round::start();
+        round.phase = phase;
+        round
+    }
+
+    #[test]
+    fn round_ids_are_unique() {
+        let ids = (0..100).map(|_| Round::start().id).collect::<Vec<_>>();
+        let mut unique = ids.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+    }
+
+    #[test]
+    fn rounds_start_started() {
+        assert_eq!(Round::start().phase, Phase::Started);
+    }
+
+    #[test]
+    fn a_round_can_be_played_to_a_win() {
+        let mut round = Round::start();
+        assert_eq!(round.advance(Step::Download), Ok(Phase::Downloading));
+        assert_eq!(round.advance(Step::Fingerprint), Ok(Phase::Fingerprinted));
+        assert_eq!(round.advance(Step::Guess), Ok(Phase::Guessed));
+        assert_eq!(round.advance(Step::Win), Ok(Phase::Won));
+        assert!(round.is_over());
+    }
+
+    #[test]
+    fn only_the_next_phase_can_be_reached_before_the_end() {
+        let forward = [
+            (Phase::Started, Step::Download, Phase::Downloading),
+            (Phase::Downloading, Step::Fingerprint, Phase::Fingerprinted),
+            (Phase::Fingerprinted, Step::Guess, Phase::Guessed),
+        ];
+
+        for phase in PHASES {
+            for step in [Step::Download, Step::Fingerprint, Step::Guess] {
+                let expected = forward
+                    .iter()
+                    .find(|(from, by, _)| *from == phase && *by == step)
+                    .map(|(_, _, to)| *to)
+                    .ok_or(IllegalStep { phase, step });
+                assert_eq!(phase.next(step), expected, "{step} while {phase}");
+            }
+        }
+    }
+
+    #[test]
+    fn unfinished_rounds_can_end_from_any_phase() {
+        for phase in PHASES.into_iter().filter(|phase| !phase.is_over()) {
+            assert_eq!(phase.next(Step::Win), Ok(Phase::Won));
+            assert_eq!(phase.next(Step::TimeOut), Ok(Phase::TimedOut));
+            assert_eq!(phase.next(Step::Abandon), Ok(Phase::Abandoned));
+        }
+    }
+
+    #[test]
+    fn finished_rounds_stay_finished() {
+        for phase in [Phase::Won, Phase::TimedOut, Phase::Abandoned, Phase::Failed] {
+            for step in STEPS {
+                let mut round = round_at(phase);
+                assert_eq!(
+                    round.advance(step),
+                    Err(IllegalStep { phase, step }),
+                    "{step} while {phase}"
+                );
+                assert_eq!(round.phase, phase);
+            }
+        }
+    }
+
+    #[test]
+    fn a_win_before_fingerprinting_ends_the_round() {
+        let mut round = round_at(Phase::Downloading);
+        assert_eq!(round.advance(Step::Win), Ok(Phase::Won));
+
+        // the download finishing afterwards can't bring the round back
+        assert_eq!(
+            round.advance(Step::Fingerprint),
+            Err(IllegalStep {
+                phase: Phase::Won,
+                step: Step::Fingerprint
+            })
+        );
+    }
+
+    #[test]
+    fn only_downloads_fail() {
+        for phase in PHASES {
+            let expected = match phase {
+                Phase::Downloading => Ok(Phase::Failed),
+                _ => Err(IllegalStep {
+                    phase,
+                    step: Step::Fail,
+                }),
+            };
+            assert_eq!(phase.next(Step::Fail), expected, "fail while {phase}");
+        }
+    }
+
+    #[test]
+    fn illegal_steps_leave_the_round_alone() {
+        let mut round = round_at(Phase::Started);
+        assert!(round.advance(Step::Guess).is_err());
+        assert_eq!(round.phase, Phase::Started);
+    }
+
+    #[test]
+    fn illegal_steps_say_what_went_wrong() {
+        assert_eq!(
+            Phase::Won.next(Step::TimeOut).unwrap_err().to_string(),
+            "can't time out a round that's won"
+        );
+    }
+}
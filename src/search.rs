@@ -0,0 +1,133 @@
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    confidence::Confidence,
+    level::{Coefficients, LevelDifficulty, LevelSet, SampleMatch},
+};
+
+/// A level a screenshot might be of.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Guess {
+    pub name: String,
+    pub distance: f32,
+}
+
+pub struct SearchResult {
+    /// the closest levels, closest first
+    pub guesses: Vec<Guess>,
+    pub confidence: Option<Confidence>,
+}
+
+impl SearchResult {
+    pub fn best(&self) -> Option<&Guess> {
+        self.guesses.first()
+    }
+
+    /// Whether this is a more convincing match than one from another
+    /// database. Distances aren't comparable between databases, so a guess
+    /// at a known level beats one that probably isn't, then the more
+    /// confident one wins.
+    pub fn beats(&self, other: &SearchResult) -> bool {
+        let rank = |result: &SearchResult| {
+            result
+                .confidence
+                .map(|confidence| (confidence.known, confidence.score))
+        };
+        rank(self) > rank(other)
+    }
+}
+
+/// The best guess from another difficulty's database.
+#[derive(Debug, Clone, Serialize)]
+pub struct DifficultyMatch {
+    pub difficulty: LevelDifficulty,
+    pub guess: Guess,
+    pub confidence: Confidence,
+}
+
+/// Finds the `k` levels closest to a fingerprint, and always at least one.
+/// Uses the set's index if it has one built for `mode`.
+pub fn search(
+    levels: &LevelSet,
+    coefficients: &Coefficients,
+    k: usize,
+    mode: SampleMatch,
+) -> SearchResult {
+    match &levels.index {
+        Some(index) if index.mode() == mode => {
+            // confidence needs the runner-up even if only one guess is wanted
+            let guesses = index
+                .search(coefficients, k.max(2))
+                .into_iter()
+                .map(|(name, distance)| Guess { name, distance })
+                .collect();
+
+            finish(levels, guesses, k)
+        }
+        _ => search_exact(levels, coefficients, k, mode),
+    }
+}
+
+/// Like `search`, but compares the fingerprint against every level, with the
+/// set's kernel if it has one built for `mode`.
+pub fn search_exact(
+    levels: &LevelSet,
+    coefficients: &Coefficients,
+    k: usize,
+    mode: SampleMatch,
+) -> SearchResult {
+    match &levels.kernel {
+        Some((kernel_mode, kernel)) if *kernel_mode == mode => {
+            let guesses = kernel
+                .search(&coefficients.0, k.max(2))
+                .into_iter()
+                .map(|(name, distance)| Guess {
+                    name: name.to_owned(),
+                    distance,
+                })
+                .collect();
+
+            finish(levels, guesses, k)
+        }
+        _ => scan(levels, coefficients, k, mode),
+    }
+}
+
+/// Compares the fingerprint against every level one by one.
+pub fn scan(
+    levels: &LevelSet,
+    coefficients: &Coefficients,
+    k: usize,
+    mode: SampleMatch,
+) -> SearchResult {
+    let measure = levels.measure();
+    let mut guesses = levels
+        .par_iter()
+        .map(|(_, level)| Guess {
+            name: level.name.to_owned(),
+            distance: level.distance_to(coefficients, &measure, mode),
+        })
+        .collect::<Vec<_>>();
+
+    guesses.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    finish(levels, guesses, k)
+}
+
+/// Rates the closest guesses and keeps `k` of them.
+fn finish(levels: &LevelSet, mut guesses: Vec<Guess>, k: usize) -> SearchResult {
+    let confidence = Confidence::new(
+        &guesses
+            .iter()
+            .take(2)
+            .map(|guess| guess.distance)
+            .collect::<Vec<_>>(),
+        levels.threshold,
+    );
+    guesses.truncate(k.max(1));
+
+    SearchResult {
+        guesses,
+        confidence,
+    }
+}
@@ -0,0 +1,247 @@
+//! Reads Sparky's embeds into the events they announce.
+
+use std::fmt::Display;
+
+use serde::Deserialize;
+
+use crate::{handler::MENTION_REGEX, level::LevelDifficulty};
+
+/// The title of a new round's embed.
+const ROUND_STARTED: &str = "Guess the Level!";
+/// The title of the embed announcing a winner.
+const ROUND_WON: &str = "Congratulations! You guessed the Level correctly!";
+/// The title of the embed announcing nobody won.
+const ROUND_TIMED_OUT: &str = "Time is up!";
+
+/// What a new round's description starts with, before the difficulty.
+const DIFFICULTY_PREFIX: &str = "**Difficulty:**";
+
+/// The parts of an embed Sparky's messages are told apart by, in the shape
+/// Discord sends them.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SparkyEmbed {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub image: Option<SparkyImage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SparkyImage {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SparkyEvent {
+    /// a new round, with the screenshot to guess
+    Started {
+        image_url: String,
+        difficulty: LevelDifficulty,
+    },
+    /// someone guessed the level
+    Won { winner: u64 },
+    /// nobody guessed the level in time
+    TimedOut,
+}
+
+/// Why an embed isn't one of Sparky's events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    MissingTitle,
+    /// the title isn't one of the events', like Sparky's other messages
+    UnknownTitle(String),
+    /// a new round without a screenshot
+    MissingImage,
+    /// a new round or a win without a description
+    MissingDescription,
+    /// a new round whose description doesn't name a difficulty we know; the
+    /// round can still be played without one
+    UnknownDifficulty {
+        image_url: String,
+        description: String,
+    },
+    /// a win whose description doesn't mention the winner
+    MissingWinner(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingTitle => write!(f, "the embed has no title"),
+            Self::UnknownTitle(title) => write!(f, "unknown title {title:?}"),
+            Self::MissingImage => write!(f, "the new round has no screenshot"),
+            Self::MissingDescription => write!(f, "the embed has no description"),
+            Self::UnknownDifficulty { description, .. } => {
+                write!(f, "could not read the difficulty from {description:?}")
+            }
+            Self::MissingWinner(description) => {
+                write!(f, "could not find the winner in {description:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Reads the difficulty out of a new round's description, which looks like
+/// `**Difficulty:** Medium`.
+fn difficulty(description: &str) -> Option<LevelDifficulty> {
+    description
+        .trim()
+        .strip_prefix(DIFFICULTY_PREFIX)?
+        .trim()
+        .to_lowercase()
+        .parse()
+        .ok()
+}
+
+pub fn parse(embed: &SparkyEmbed) -> Result<SparkyEvent, ParseError> {
+    let title = embed.title.as_deref().ok_or(ParseError::MissingTitle)?;
+    let description = embed.description.as_deref();
+
+    match title {
+        ROUND_STARTED => {
+            let image_url = embed
+                .image
+                .as_ref()
+                .ok_or(ParseError::MissingImage)?
+                .url
+                .to_owned();
+            let description = description.ok_or(ParseError::MissingDescription)?;
+            match difficulty(description) {
+                Some(difficulty) => Ok(SparkyEvent::Started {
+                    image_url,
+                    difficulty,
+                }),
+                None => Err(ParseError::UnknownDifficulty {
+                    image_url,
+                    description: description.to_owned(),
+                }),
+            }
+        }
+
+        ROUND_WON => {
+            let description = description.ok_or(ParseError::MissingDescription)?;
+            MENTION_REGEX
+                .captures(description)
+                .and_then(|captures| captures[1].parse().ok())
+                .map(|winner| SparkyEvent::Won { winner })
+                .ok_or_else(|| ParseError::MissingWinner(description.to_owned()))
+        }
+
+        ROUND_TIMED_OUT => Ok(SparkyEvent::TimedOut),
+
+        _ => Err(ParseError::UnknownTitle(title.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_URL: &str =
+        "https://cdn.discordapp.com/attachments/1089561234567890123/1201234567890123456/image.png";
+
+    /// Parses one of the embeds captured in `tests/fixtures/embeds`.
+    fn parse_fixture(fixture: &str) -> Result<SparkyEvent, ParseError> {
+        let path = format!(
+            "{}/tests/fixtures/embeds/{fixture}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let embed: SparkyEmbed = serde_json::from_str(&std::fs::read_to_string(path).unwrap())
+            .unwrap_or_else(|err| panic!("{fixture} is not an embed: {err}"));
+        parse(&embed)
+    }
+
+    #[test]
+    fn round_started() {
+        assert_eq!(
+            parse_fixture("round_started"),
+            Ok(SparkyEvent::Started {
+                image_url: IMAGE_URL.to_owned(),
+                difficulty: LevelDifficulty::Medium,
+            })
+        );
+    }
+
+    #[test]
+    fn round_started_with_untidy_difficulty() {
+        assert_eq!(
+            parse_fixture("round_started_legendary"),
+            Ok(SparkyEvent::Started {
+                image_url: IMAGE_URL.to_owned(),
+                difficulty: LevelDifficulty::Legendary,
+            })
+        );
+    }
+
+    #[test]
+    fn round_won() {
+        assert_eq!(
+            parse_fixture("round_won"),
+            Ok(SparkyEvent::Won {
+                winner: 412345678901234567
+            })
+        );
+    }
+
+    #[test]
+    fn round_timed_out() {
+        assert_eq!(parse_fixture("round_timed_out"), Ok(SparkyEvent::TimedOut));
+    }
+
+    #[test]
+    fn unknown_title() {
+        assert_eq!(
+            parse_fixture("unknown_title"),
+            Err(ParseError::UnknownTitle("Leaderboard".to_owned()))
+        );
+    }
+
+    #[test]
+    fn missing_title() {
+        assert_eq!(
+            parse_fixture("missing_title"),
+            Err(ParseError::MissingTitle)
+        );
+    }
+
+    #[test]
+    fn missing_image() {
+        assert_eq!(
+            parse_fixture("missing_image"),
+            Err(ParseError::MissingImage)
+        );
+    }
+
+    #[test]
+    fn missing_description() {
+        assert_eq!(
+            parse_fixture("missing_description"),
+            Err(ParseError::MissingDescription)
+        );
+    }
+
+    #[test]
+    fn unknown_difficulty() {
+        assert_eq!(
+            parse_fixture("unknown_difficulty"),
+            Err(ParseError::UnknownDifficulty {
+                image_url: IMAGE_URL.to_owned(),
+                description: "**Difficulty:** Impossible".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn missing_winner() {
+        assert_eq!(
+            parse_fixture("missing_winner"),
+            Err(ParseError::MissingWinner(
+                "Someone guessed the level!".to_owned()
+            ))
+        );
+    }
+}
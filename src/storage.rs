@@ -0,0 +1,870 @@
+//! Where level databases and the history of games are kept: the `.bin` files
+//! (with aliases next to them and games in a JSONL file), or a SQLite database.
+
+use std::{collections::BTreeMap, path::Path, str::FromStr, sync::Arc};
+
+use colored::Colorize;
+use lazy_static::lazy_static;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+use crate::{
+    handler::MENTION_REGEX,
+    level::{
+        read_weights, Coefficients, Database, DatabaseError, FingerprintKind, Level,
+        LevelDifficulty, LevelMetadata, LevelSet, DATABASE_VERSION,
+    },
+};
+
+lazy_static! {
+    static ref SAVE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Where `BinStorage` appends finished games.
+pub const GAMES_FILENAME: &str = "games.jsonl";
+
+/// How a game went for us.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// someone won and our best guess was their answer
+    Correct,
+    /// someone won with another answer
+    Incorrect,
+    /// nobody won
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Correct => "correct",
+            Self::Incorrect => "incorrect",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+impl FromStr for Outcome {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "correct" => Self::Correct,
+            "incorrect" => Self::Incorrect,
+            "timed_out" => Self::TimedOut,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// A finished game in a channel.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Game {
+    pub channel_id: String,
+    /// the directory name of the difficulty the round was filed under
+    pub difficulty: String,
+    /// our best guess, if we had one
+    pub guess: Option<String>,
+    /// how far the screenshot was from our best guess
+    pub distance: Option<f32>,
+    /// the winning answer
+    pub answer: Option<String>,
+    pub outcome: Outcome,
+    /// in seconds since the Unix epoch
+    pub started_at: u64,
+    /// in seconds since the Unix epoch
+    pub ended_at: u64,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Reads a difficulty's levels and aliases, creating its image folder.
+    /// Panics if they can't be read.
+    async fn read_levels(&self, difficulty: LevelDifficulty) -> LevelSet;
+
+    /// Reads a difficulty's levels and aliases like `read_levels`, but without
+    /// writing anything, for dry runs: outdated databases aren't migrated and
+    /// damaged ones aren't moved aside.
+    async fn peek_levels(&self, difficulty: LevelDifficulty) -> LevelSet;
+
+    /// Reads a difficulty's levels and aliases without writing anything, and
+    /// keeping samples of whatever size they were stored with, so a database
+    /// this build can't search can still be rebuilt with its names and
+    /// metadata.
+    async fn read_records(&self, difficulty: LevelDifficulty) -> anyhow::Result<LevelSet>;
+
+    /// Replaces everything stored for a difficulty with `levels`.
+    async fn save_levels(
+        &self,
+        difficulty: LevelDifficulty,
+        levels: &LevelSet,
+    ) -> anyhow::Result<()>;
+
+    /// Saves a level that was added, changed or removed (along with the
+    /// aliases), rewriting the whole difficulty unless the storage can do
+    /// better.
+    async fn save_level(
+        &self,
+        difficulty: LevelDifficulty,
+        levels: &LevelSet,
+        _name: &str,
+    ) -> anyhow::Result<()> {
+        self.save_levels(difficulty, levels).await
+    }
+
+    async fn record_game(&self, game: &Game) -> anyhow::Result<()>;
+
+    /// Every recorded game, oldest first.
+    async fn games(&self) -> anyhow::Result<Vec<Game>>;
+}
+
+/// The `.bin` databases, rewritten whole on every save.
+pub struct BinStorage;
+
+/// Reads and parses a database file, returning `None` if it doesn't exist.
+async fn read_database_file(filename: &str) -> anyhow::Result<Option<Database>> {
+    if !Path::new(filename).exists() {
+        return Ok(None);
+    }
+
+    let data = tokio::fs::read(filename).await?;
+    Ok(Some(Database::parse(&data)?))
+}
+
+/// Whether reading a database failed because it's damaged, rather than
+/// unusable by this build or unreadable.
+fn is_corrupt(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<DatabaseError>(),
+        Some(DatabaseError::Corrupt(_))
+    )
+}
+
+/// Reads the aliases of a difficulty, if it has any.
+fn read_aliases(difficulty: LevelDifficulty) -> BTreeMap<String, String> {
+    let filename = difficulty.aliases_filename();
+    match std::fs::read(&filename) {
+        Ok(data) => serde_json::from_slice(&data)
+            .unwrap_or_else(|err| panic!("failed to read {filename}: {err}")),
+        Err(_) => BTreeMap::new(),
+    }
+}
+
+/// The names of a difficulty's levels that a pre-version 2 writer mangled.
+///
+/// Only the legacy records themselves show this, so they're read from the
+/// `.bin` database if it hasn't been migrated yet, and otherwise from the
+/// originals the migration kept. Files that can't be read are skipped with a
+/// warning, since they're what a repair is usually run on.
+pub async fn mangled_names(difficulty: LevelDifficulty) -> Vec<String> {
+    let filename = difficulty.filename();
+    let mut names = vec![];
+    for source in [
+        filename.to_owned(),
+        format!("{filename}.v0"),
+        format!("{filename}.v1"),
+    ] {
+        let database = match read_database_file(&source).await {
+            Ok(Some(database)) => database,
+            Ok(None) => continue,
+            Err(err) => {
+                println!(
+                    "{} skipping {} while looking for mangled names: {:#}",
+                    "warning!".yellow().bold(),
+                    source,
+                    err
+                );
+                continue;
+            }
+        };
+        for name in database.mangled {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+impl BinStorage {
+    /// Reads a difficulty's levels, only moving a damaged primary aside and
+    /// migrating an outdated database if `write` is set.
+    async fn load(&self, difficulty: LevelDifficulty, write: bool) -> LevelSet {
+        // create image difficulty folders
+        if write {
+            tokio::fs::create_dir_all(format!("levels/{}", difficulty.directory()))
+                .await
+                .unwrap();
+        }
+
+        let mut levels = vec![];
+
+        // fall back to the previous generation if the primary is damaged,
+        // moving it out of the way so the next save doesn't rotate it over
+        // the good backup. a database this build can't use isn't damaged, and
+        // neither would its backup be
+        let filename = difficulty.filename();
+        let backup_filename = difficulty.backup_filename();
+        let temp_filename = format!("{filename}.tmp");
+        let (source, database) = match read_database_file(filename).await {
+            Ok(Some(database)) => {
+                if Path::new(&temp_filename).exists() {
+                    println!(
+                        "{} ignoring {}, left by a save that didn't finish",
+                        "warning!".yellow().bold(),
+                        temp_filename
+                    );
+                }
+                (filename.to_owned(), Some(database))
+            }
+            // a crash between the renames in `save_levels` leaves the newest
+            // generation in the temp file and none in the primary
+            Ok(None) => match read_database_file(&temp_filename).await {
+                Ok(Some(database)) => {
+                    println!(
+                        "{} {} is missing, {} the interrupted save in {}",
+                        "warning!".yellow().bold(),
+                        filename,
+                        if write { "finished" } else { "would finish" },
+                        temp_filename
+                    );
+                    if write {
+                        tokio::fs::rename(&temp_filename, filename).await.unwrap();
+                        (filename.to_owned(), Some(database))
+                    } else {
+                        (temp_filename, Some(database))
+                    }
+                }
+                result => {
+                    if let Err(err) = result {
+                        println!(
+                            "{} ignoring {}: {:#}",
+                            "warning!".yellow().bold(),
+                            temp_filename,
+                            err
+                        );
+                    }
+                    (
+                        backup_filename.to_owned(),
+                        read_database_file(&backup_filename)
+                            .await
+                            .unwrap_or_else(|err| {
+                                panic!("failed to read {backup_filename}: {err:#}")
+                            }),
+                    )
+                }
+            },
+            Err(err) if is_corrupt(&err) => {
+                let corrupt_filename = format!("{filename}.corrupt");
+                println!(
+                    "{} failed to read {}: {:#}, {} {} and falling back to {}",
+                    "warning!".yellow().bold(),
+                    filename,
+                    err,
+                    if write {
+                        "moved it to"
+                    } else {
+                        "would move it to"
+                    },
+                    corrupt_filename,
+                    backup_filename
+                );
+                if write {
+                    tokio::fs::rename(filename, &corrupt_filename)
+                        .await
+                        .unwrap();
+                }
+
+                match read_database_file(&backup_filename).await {
+                    Ok(Some(database)) => (backup_filename.to_owned(), Some(database)),
+                    Ok(None) => panic!("failed to read {filename} and there is no backup"),
+                    Err(err) => panic!("failed to read {backup_filename}: {err:#}"),
+                }
+            }
+            Err(err) => panic!("can't use {filename}: {err:#}"),
+        };
+
+        let mut version = DATABASE_VERSION;
+        let mut fingerprint = FingerprintKind::default();
+        if let Some(database) = database {
+            version = database.version();
+            fingerprint = database.fingerprint();
+            if source != filename {
+                println!(
+                    "{} restored {} from {}",
+                    "warning!".yellow().bold(),
+                    difficulty,
+                    source
+                );
+            }
+
+            if !database.mangled.is_empty() {
+                println!(
+                    "{} {} {} level names were mangled by an older version, run `repair` to recover them",
+                    "warning!".yellow().bold(),
+                    database.mangled.len(),
+                    difficulty
+                );
+            }
+
+            for level in database.levels {
+                if level.name.starts_with("s?") || MENTION_REGEX.is_match(&level.name) {
+                    continue;
+                }
+
+                levels.push((level.name.to_owned(), level));
+            }
+            println!("read in {} {} levels", levels.len(), difficulty);
+        }
+
+        let levels = LevelSet {
+            fingerprint,
+            levels: levels.into_iter().collect(),
+            weights: read_weights(difficulty, fingerprint).map(Arc::new),
+            aliases: read_aliases(difficulty),
+            ..Default::default()
+        };
+
+        // rewrite outdated databases in the current format, keeping the
+        // original around
+        if write && version < DATABASE_VERSION {
+            let old_filename = format!("{}.v{}", filename, version);
+            tokio::fs::copy(&source, &old_filename).await.unwrap();
+            self.save_levels(difficulty, &levels).await.unwrap();
+            println!(
+                "migrated {} database from version {} to {} (original kept as {})",
+                difficulty, version, DATABASE_VERSION, old_filename
+            );
+        }
+
+        levels
+    }
+}
+
+#[async_trait]
+impl Storage for BinStorage {
+    async fn read_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        self.load(difficulty, true).await
+    }
+
+    async fn peek_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        self.load(difficulty, false).await
+    }
+
+    async fn read_records(&self, difficulty: LevelDifficulty) -> anyhow::Result<LevelSet> {
+        let filename = difficulty.filename();
+        let source = if Path::new(filename).exists() {
+            filename.to_owned()
+        } else {
+            difficulty.backup_filename()
+        };
+
+        let database = if Path::new(&source).exists() {
+            Some(Database::parse_records(&tokio::fs::read(&source).await?)?)
+        } else {
+            None
+        };
+
+        Ok(LevelSet {
+            fingerprint: database
+                .as_ref()
+                .map_or_else(FingerprintKind::default, Database::fingerprint),
+            levels: database
+                .into_iter()
+                .flat_map(|database| database.levels)
+                .map(|level| (level.name.to_owned(), level))
+                .collect(),
+            aliases: read_aliases(difficulty),
+            ..Default::default()
+        })
+    }
+
+    async fn save_levels(
+        &self,
+        difficulty: LevelDifficulty,
+        levels: &LevelSet,
+    ) -> anyhow::Result<()> {
+        let data = Database::serialize(levels)?;
+
+        // saves from different channels must not share the temp file
+        let _guard = SAVE_LOCK.lock().await;
+        let filename = difficulty.filename();
+        let temp_filename = format!("{filename}.tmp");
+
+        // write and sync the new generation before touching the current one
+        {
+            let mut file = tokio::fs::File::create(&temp_filename).await?;
+            file.write_all(&data).await?;
+            file.sync_all().await?;
+        }
+
+        // keep the current generation as a backup, then swap in the new one
+        if Path::new(filename).exists() {
+            tokio::fs::rename(filename, difficulty.backup_filename()).await?;
+        }
+        tokio::fs::rename(&temp_filename, filename).await?;
+
+        // aliases are small and rarely change, so they're simply rewritten
+        let aliases_filename = difficulty.aliases_filename();
+        if !levels.aliases.is_empty() || Path::new(&aliases_filename).exists() {
+            tokio::fs::write(
+                &aliases_filename,
+                serde_json::to_vec_pretty(&levels.aliases).unwrap(),
+            )
+            .await?;
+        }
+
+        // make the renames durable
+        #[cfg(unix)]
+        tokio::fs::File::open(".").await?.sync_all().await?;
+
+        Ok(())
+    }
+
+    async fn record_game(&self, game: &Game) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(game)?;
+        line.push(b'\n');
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(GAMES_FILENAME)
+            .await?
+            .write_all(&line)
+            .await?;
+        Ok(())
+    }
+
+    async fn games(&self) -> anyhow::Result<Vec<Game>> {
+        if !tokio::fs::try_exists(GAMES_FILENAME).await? {
+            return Ok(vec![]);
+        }
+
+        tokio::fs::read_to_string(GAMES_FILENAME)
+            .await?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+}
+
+/// A SQLite database holding every difficulty's levels, their aliases and the
+/// games played, where a learned answer only rewrites its own level.
+pub struct SqliteStorage {
+    connection: Arc<std::sync::Mutex<Connection>>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS databases (
+    difficulty TEXT PRIMARY KEY,
+    fingerprint TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS levels (
+    difficulty TEXT NOT NULL,
+    name TEXT NOT NULL,
+    level_id INTEGER,
+    creator TEXT,
+    first_seen INTEGER,
+    last_seen INTEGER,
+    times_seen INTEGER NOT NULL,
+    times_guessed INTEGER NOT NULL,
+    PRIMARY KEY (difficulty, name)
+);
+CREATE TABLE IF NOT EXISTS samples (
+    difficulty TEXT NOT NULL,
+    name TEXT NOT NULL,
+    sample INTEGER NOT NULL,
+    coefficients BLOB NOT NULL,
+    PRIMARY KEY (difficulty, name, sample)
+);
+CREATE TABLE IF NOT EXISTS aliases (
+    difficulty TEXT NOT NULL,
+    alias TEXT NOT NULL,
+    level TEXT NOT NULL,
+    PRIMARY KEY (difficulty, alias)
+);
+CREATE TABLE IF NOT EXISTS games (
+    id INTEGER PRIMARY KEY,
+    channel_id TEXT NOT NULL,
+    difficulty TEXT NOT NULL,
+    guess TEXT,
+    distance REAL,
+    answer TEXT,
+    outcome TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    ended_at INTEGER NOT NULL
+);
+";
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            connection: Arc::new(std::sync::Mutex::new(connection)),
+        })
+    }
+
+    /// Runs `f` with the connection on a blocking thread, so queries don't
+    /// hold up the runtime.
+    async fn with_connection<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let connection = self.connection.clone();
+        tokio::task::spawn_blocking(move || f(&mut connection.lock().unwrap())).await?
+    }
+
+    /// Reads a difficulty, checking that its samples fit its fingerprint
+    /// unless `any_size` is set.
+    async fn read(&self, difficulty: LevelDifficulty, any_size: bool) -> anyhow::Result<LevelSet> {
+        self.with_connection(move |connection| Self::read_with(connection, difficulty, any_size))
+            .await
+    }
+
+    fn read_with(
+        connection: &Connection,
+        difficulty: LevelDifficulty,
+        any_size: bool,
+    ) -> anyhow::Result<LevelSet> {
+        let d = difficulty.directory();
+
+        let fingerprint = match connection
+            .query_row(
+                "SELECT fingerprint FROM databases WHERE difficulty = ?1",
+                [d],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+        {
+            Some(fingerprint) => fingerprint
+                .parse()
+                .map_err(|_| anyhow::anyhow!("unknown fingerprint {fingerprint}"))?,
+            None => FingerprintKind::default(),
+        };
+        let num_values = fingerprint.num_values();
+
+        let mut samples: BTreeMap<String, Vec<Coefficients>> = BTreeMap::new();
+        let mut statement = connection.prepare(
+            "SELECT name, coefficients FROM samples WHERE difficulty = ?1 ORDER BY name, sample",
+        )?;
+        let mut rows = statement.query([d])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let blob: Vec<u8> = row.get(1)?;
+            anyhow::ensure!(
+                any_size || blob.len() == num_values * 4,
+                "{name} has a sample of {} bytes, {fingerprint} needs {}",
+                blob.len(),
+                num_values * 4
+            );
+            let values = blob
+                .chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            samples.entry(name).or_default().push(Coefficients(values));
+        }
+
+        let mut levels = LevelSet {
+            fingerprint,
+            weights: read_weights(difficulty, fingerprint).map(Arc::new),
+            ..Default::default()
+        };
+        let mut statement = connection.prepare(
+            "SELECT name, level_id, creator, first_seen, last_seen, times_seen, times_guessed
+             FROM levels WHERE difficulty = ?1",
+        )?;
+        let mut rows = statement.query([d])?;
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let Some(samples) = samples.remove(&name) else {
+                println!(
+                    "{} {} has no samples, skipping it",
+                    "warning!".yellow().bold(),
+                    difficulty.colorize(name.as_str())
+                );
+                continue;
+            };
+
+            let level = Level {
+                name: name.to_owned(),
+                difficulty,
+                centroid: Coefficients::mean(samples.iter()),
+                samples,
+                metadata: LevelMetadata {
+                    level_id: row.get(1)?,
+                    creator: row.get(2)?,
+                    first_seen: row.get(3)?,
+                    last_seen: row.get(4)?,
+                    times_seen: row.get(5)?,
+                    times_guessed: row.get(6)?,
+                },
+            };
+            levels.insert(name, level);
+        }
+
+        let mut statement =
+            connection.prepare("SELECT alias, level FROM aliases WHERE difficulty = ?1")?;
+        levels.aliases = statement
+            .query_map([d], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(levels)
+    }
+
+    /// Writes one level and its samples, replacing whatever was stored for it.
+    fn write_level(
+        transaction: &rusqlite::Transaction,
+        difficulty: LevelDifficulty,
+        level: &Level,
+    ) -> rusqlite::Result<()> {
+        let d = difficulty.directory();
+        transaction.execute(
+            "INSERT OR REPLACE INTO levels
+             (difficulty, name, level_id, creator, first_seen, last_seen, times_seen, times_guessed)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                d,
+                level.name,
+                level.metadata.level_id,
+                level.metadata.creator,
+                level.metadata.first_seen,
+                level.metadata.last_seen,
+                level.metadata.times_seen,
+                level.metadata.times_guessed,
+            ],
+        )?;
+        transaction.execute(
+            "DELETE FROM samples WHERE difficulty = ?1 AND name = ?2",
+            params![d, level.name],
+        )?;
+        for (i, sample) in level.samples.iter().enumerate() {
+            let blob = sample
+                .0
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect::<Vec<_>>();
+            transaction.execute(
+                "INSERT INTO samples (difficulty, name, sample, coefficients) VALUES (?1, ?2, ?3, ?4)",
+                params![d, level.name, i, blob],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Records the fingerprint and rewrites the aliases.
+    fn write_header(
+        transaction: &rusqlite::Transaction,
+        difficulty: LevelDifficulty,
+        fingerprint: FingerprintKind,
+        aliases: &BTreeMap<String, String>,
+    ) -> rusqlite::Result<()> {
+        let d = difficulty.directory();
+        transaction.execute(
+            "INSERT OR REPLACE INTO databases (difficulty, fingerprint) VALUES (?1, ?2)",
+            params![d, fingerprint.to_string()],
+        )?;
+        transaction.execute("DELETE FROM aliases WHERE difficulty = ?1", [d])?;
+        for (alias, level) in aliases {
+            transaction.execute(
+                "INSERT INTO aliases (difficulty, alias, level) VALUES (?1, ?2, ?3)",
+                params![d, alias, level],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn read_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        // screenshots are still kept as files
+        tokio::fs::create_dir_all(format!("levels/{}", difficulty.directory()))
+            .await
+            .unwrap();
+
+        let levels = self
+            .read(difficulty, false)
+            .await
+            .unwrap_or_else(|err| panic!("failed to read {difficulty} levels: {err:#}"));
+        if !levels.is_empty() {
+            println!("read in {} {} levels", levels.len(), difficulty);
+        }
+        levels
+    }
+
+    async fn peek_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        self.read(difficulty, false)
+            .await
+            .unwrap_or_else(|err| panic!("failed to read {difficulty} levels: {err:#}"))
+    }
+
+    async fn read_records(&self, difficulty: LevelDifficulty) -> anyhow::Result<LevelSet> {
+        self.read(difficulty, true).await
+    }
+
+    async fn save_levels(
+        &self,
+        difficulty: LevelDifficulty,
+        levels: &LevelSet,
+    ) -> anyhow::Result<()> {
+        let fingerprint = levels.fingerprint;
+        let aliases = levels.aliases.clone();
+        let levels = levels.values().cloned().collect::<Vec<_>>();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let d = difficulty.directory();
+            transaction.execute("DELETE FROM levels WHERE difficulty = ?1", [d])?;
+            transaction.execute("DELETE FROM samples WHERE difficulty = ?1", [d])?;
+            Self::write_header(&transaction, difficulty, fingerprint, &aliases)?;
+            for level in &levels {
+                Self::write_level(&transaction, difficulty, level)?;
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn save_level(
+        &self,
+        difficulty: LevelDifficulty,
+        levels: &LevelSet,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        let fingerprint = levels.fingerprint;
+        let aliases = levels.aliases.clone();
+        let level = levels.get(name).cloned();
+        let name = name.to_owned();
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction()?;
+            let d = difficulty.directory();
+            Self::write_header(&transaction, difficulty, fingerprint, &aliases)?;
+            match level {
+                Some(level) => Self::write_level(&transaction, difficulty, &level)?,
+                None => {
+                    transaction.execute(
+                        "DELETE FROM levels WHERE difficulty = ?1 AND name = ?2",
+                        params![d, name],
+                    )?;
+                    transaction.execute(
+                        "DELETE FROM samples WHERE difficulty = ?1 AND name = ?2",
+                        params![d, name],
+                    )?;
+                }
+            }
+            transaction.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn record_game(&self, game: &Game) -> anyhow::Result<()> {
+        let game = game.clone();
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO games
+                 (channel_id, difficulty, guess, distance, answer, outcome, started_at, ended_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    game.channel_id,
+                    game.difficulty,
+                    game.guess,
+                    game.distance,
+                    game.answer,
+                    game.outcome.as_str(),
+                    game.started_at,
+                    game.ended_at,
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn games(&self) -> anyhow::Result<Vec<Game>> {
+        self.with_connection(|connection| {
+            let mut statement = connection.prepare(
+                "SELECT channel_id, difficulty, guess, distance, answer, outcome, started_at, ended_at
+                 FROM games ORDER BY id",
+            )?;
+            let games = statement
+                .query_map([], |row| {
+                    let outcome: String = row.get(5)?;
+                    Ok(Game {
+                        channel_id: row.get(0)?,
+                        difficulty: row.get(1)?,
+                        guess: row.get(2)?,
+                        distance: row.get(3)?,
+                        answer: row.get(4)?,
+                        outcome: outcome.parse().map_err(|_| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                5,
+                                rusqlite::types::Type::Text,
+                                format!("unknown outcome {outcome}").into(),
+                            )
+                        })?,
+                        started_at: row.get(6)?,
+                        ended_at: row.get(7)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(games)
+        })
+        .await
+    }
+}
+
+/// Reads levels from another storage without writing to it, keeping games in
+/// memory, so a simulation can play without changing the real databases.
+pub struct MemoryStorage {
+    inner: Arc<dyn Storage>,
+    games: Mutex<Vec<Game>>,
+}
+
+impl MemoryStorage {
+    pub fn new(inner: Arc<dyn Storage>) -> Self {
+        Self {
+            inner,
+            games: Mutex::new(vec![]),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn read_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        self.inner.peek_levels(difficulty).await
+    }
+
+    async fn peek_levels(&self, difficulty: LevelDifficulty) -> LevelSet {
+        self.inner.peek_levels(difficulty).await
+    }
+
+    async fn read_records(&self, difficulty: LevelDifficulty) -> anyhow::Result<LevelSet> {
+        self.inner.read_records(difficulty).await
+    }
+
+    async fn save_levels(
+        &self,
+        _difficulty: LevelDifficulty,
+        _levels: &LevelSet,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn record_game(&self, game: &Game) -> anyhow::Result<()> {
+        self.games.lock().await.push(game.clone());
+        Ok(())
+    }
+
+    async fn games(&self) -> anyhow::Result<Vec<Game>> {
+        let mut games = self.inner.games().await?;
+        games.extend(self.games.lock().await.iter().cloned());
+        Ok(games)
+    }
+}
@@ -0,0 +1,347 @@
+//! Plain-text copies of level databases in JSON or CSV, so they can be shared,
+//! reviewed and merged into other databases.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::level::{
+    Coefficients, FingerprintKind, Level, LevelDifficulty, LevelMetadata, LevelSet,
+};
+
+/// The file formats databases can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// every difficulty's levels, samples, metadata and aliases
+    Json,
+    /// one row per sample, repeating the level's metadata; no aliases
+    Csv,
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "json" => Self::Json,
+            "csv" => Self::Csv,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl Format {
+    /// The format a file's extension names.
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path).extension()?.to_str()?.parse().ok()
+    }
+}
+
+/// What to do with an imported level whose name is already taken.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// keep the existing level
+    #[default]
+    Skip,
+    /// replace the existing level
+    Overwrite,
+    /// keep the existing level and add the imported one under a new name
+    KeepBoth,
+}
+
+impl FromStr for Conflict {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "skip" => Self::Skip,
+            "overwrite" => Self::Overwrite,
+            "keep-both" => Self::KeepBoth,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// One difficulty's database.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedDatabase {
+    pub difficulty: String,
+    pub fingerprint: FingerprintKind,
+    pub levels: Vec<ExportedLevel>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportedLevel {
+    pub name: String,
+    /// oldest first
+    pub samples: Vec<Vec<f32>>,
+    #[serde(default)]
+    pub metadata: LevelMetadata,
+}
+
+/// A CSV row: one sample of a level.
+#[derive(Debug, Deserialize, Serialize)]
+struct Row {
+    difficulty: String,
+    fingerprint: FingerprintKind,
+    name: String,
+    /// the sample's position among the level's samples, oldest first
+    sample: usize,
+    level_id: Option<u64>,
+    creator: Option<String>,
+    first_seen: Option<u64>,
+    last_seen: Option<u64>,
+    times_seen: u32,
+    times_guessed: u32,
+    /// separated by spaces
+    coefficients: String,
+}
+
+/// Copies a database, levels sorted by name.
+pub fn export(difficulty: LevelDifficulty, levels: &LevelSet) -> ExportedDatabase {
+    let mut exported = levels
+        .values()
+        .map(|level| ExportedLevel {
+            name: level.name.to_owned(),
+            samples: level
+                .samples
+                .iter()
+                .map(|sample| sample.0.clone())
+                .collect(),
+            metadata: level.metadata.clone(),
+        })
+        .collect::<Vec<_>>();
+    exported.sort_by(|a, b| a.name.cmp(&b.name));
+
+    ExportedDatabase {
+        difficulty: difficulty.directory().to_owned(),
+        fingerprint: levels.fingerprint,
+        levels: exported,
+        aliases: levels.aliases.clone(),
+    }
+}
+
+pub fn write(path: &str, format: Format, databases: &[ExportedDatabase]) -> anyhow::Result<()> {
+    match format {
+        Format::Json => std::fs::write(path, serde_json::to_string_pretty(databases)?)?,
+        Format::Csv => {
+            let mut writer = csv::Writer::from_path(path)?;
+            for database in databases {
+                for level in &database.levels {
+                    for (i, sample) in level.samples.iter().enumerate() {
+                        writer.serialize(Row {
+                            difficulty: database.difficulty.to_owned(),
+                            fingerprint: database.fingerprint,
+                            name: level.name.to_owned(),
+                            sample: i,
+                            level_id: level.metadata.level_id,
+                            creator: level.metadata.creator.clone(),
+                            first_seen: level.metadata.first_seen,
+                            last_seen: level.metadata.last_seen,
+                            times_seen: level.metadata.times_seen,
+                            times_guessed: level.metadata.times_guessed,
+                            coefficients: sample
+                                .iter()
+                                .map(f32::to_string)
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                        })?;
+                    }
+                }
+            }
+            writer.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read(path: &str, format: Format) -> anyhow::Result<Vec<ExportedDatabase>> {
+    match format {
+        Format::Json => Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?),
+        Format::Csv => {
+            let mut databases: Vec<ExportedDatabase> = vec![];
+            // where each level is in its database, by difficulty and name
+            let mut positions: HashMap<(String, String), usize> = HashMap::new();
+            // each level's samples by position
+            let mut samples: HashMap<(usize, usize), BTreeMap<usize, Vec<f32>>> = HashMap::new();
+
+            for (line, row) in csv::Reader::from_path(path)?.deserialize().enumerate() {
+                let row: Row = row.map_err(|err| anyhow::anyhow!("row {}: {}", line + 1, err))?;
+                let coefficients = row
+                    .coefficients
+                    .split_whitespace()
+                    .map(f32::from_str)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| anyhow::anyhow!("row {}: {}", line + 1, err))?;
+
+                let d = match databases
+                    .iter()
+                    .position(|database| database.difficulty == row.difficulty)
+                {
+                    Some(d) => d,
+                    None => {
+                        databases.push(ExportedDatabase {
+                            difficulty: row.difficulty.to_owned(),
+                            fingerprint: row.fingerprint,
+                            levels: vec![],
+                            aliases: BTreeMap::new(),
+                        });
+                        databases.len() - 1
+                    }
+                };
+                let database = &mut databases[d];
+                anyhow::ensure!(
+                    database.fingerprint == row.fingerprint,
+                    "row {}: {} levels use both {} and {}",
+                    line + 1,
+                    row.difficulty,
+                    database.fingerprint,
+                    row.fingerprint
+                );
+
+                let key = (row.difficulty.to_owned(), row.name.to_owned());
+                let l = match positions.get(&key) {
+                    Some(l) => *l,
+                    None => {
+                        positions.insert(key, database.levels.len());
+                        database.levels.push(ExportedLevel {
+                            name: row.name,
+                            samples: vec![],
+                            metadata: LevelMetadata {
+                                level_id: row.level_id,
+                                creator: row.creator,
+                                first_seen: row.first_seen,
+                                last_seen: row.last_seen,
+                                times_seen: row.times_seen,
+                                times_guessed: row.times_guessed,
+                            },
+                        });
+                        database.levels.len() - 1
+                    }
+                };
+                samples
+                    .entry((d, l))
+                    .or_default()
+                    .insert(row.sample, coefficients);
+            }
+
+            for ((d, l), samples) in samples {
+                databases[d].levels[l].samples = samples.into_values().collect();
+            }
+            Ok(databases)
+        }
+    }
+}
+
+/// What an import did.
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub skipped: usize,
+    pub overwritten: usize,
+    /// levels kept alongside an existing one, as (imported name, new name)
+    pub renamed: Vec<(String, String)>,
+}
+
+/// The first free name of the form `name (n)`.
+fn free_name(levels: &LevelSet, name: &str) -> String {
+    (2..)
+        .map(|n| format!("{name} ({n})"))
+        .find(|candidate| levels.resolve(candidate).is_none())
+        .unwrap()
+}
+
+/// Adds an exported database's levels and aliases to a set, settling name
+/// clashes as `conflict` says. An empty set takes on the export's fingerprint;
+/// otherwise they have to match. Nothing is changed if the export is invalid.
+pub fn import(
+    difficulty: LevelDifficulty,
+    levels: &mut LevelSet,
+    database: ExportedDatabase,
+    conflict: Conflict,
+) -> anyhow::Result<ImportSummary> {
+    anyhow::ensure!(
+        levels.is_empty() || levels.fingerprint == database.fingerprint,
+        "the export uses {} but the {} database uses {}",
+        database.fingerprint,
+        difficulty,
+        levels.fingerprint
+    );
+    let num_values = database.fingerprint.num_values();
+    for level in &database.levels {
+        anyhow::ensure!(!level.samples.is_empty(), "{} has no samples", level.name);
+        for sample in &level.samples {
+            anyhow::ensure!(
+                sample.len() == num_values,
+                "{} has a sample with {} values, {} needs {}",
+                level.name,
+                sample.len(),
+                database.fingerprint,
+                num_values
+            );
+        }
+    }
+
+    if levels.is_empty() && levels.fingerprint != database.fingerprint {
+        levels.fingerprint = database.fingerprint;
+        levels.weights = None;
+    }
+
+    let mut summary = ImportSummary::default();
+    let mut renames = HashMap::new();
+    for exported in database.levels {
+        let mut samples = exported.samples.into_iter().map(Coefficients);
+        let mut level = Level::new(exported.name, difficulty, samples.next().unwrap());
+        for sample in samples {
+            level.add_sample(sample);
+        }
+        level.metadata = exported.metadata;
+
+        // a clash is anything the name would be looked up as: a level under
+        // another spelling or an alias as much as the exact name
+        if let Some(existing) = levels.resolve(&level.name).map(str::to_owned) {
+            match conflict {
+                Conflict::Skip => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                Conflict::Overwrite => {
+                    levels.remove(&existing);
+                    levels.remove_alias(&level.name);
+                    levels.retarget_aliases(&existing, Some(&level.name));
+                    summary.overwritten += 1;
+                }
+                Conflict::KeepBoth => {
+                    let name = free_name(levels, &level.name);
+                    renames.insert(level.name.to_owned(), name.to_owned());
+                    summary.renamed.push((level.name, name.to_owned()));
+                    level.name = name;
+                }
+            }
+        } else {
+            summary.added += 1;
+        }
+
+        levels.insert(level.name.to_owned(), level);
+    }
+
+    // existing aliases win unless overwriting; ones that no longer make sense
+    // here (their level is missing, or they're another level's name) are
+    // dropped
+    for (alias, level) in database.aliases {
+        if levels.resolve(&alias).is_some() && conflict != Conflict::Overwrite {
+            continue;
+        }
+        let level = renames.get(&level).unwrap_or(&level);
+        levels.add_alias(&alias, level).ok();
+    }
+
+    Ok(summary)
+}
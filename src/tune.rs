@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    confidence,
+    eval::Query,
+    level::{LevelSet, SampleMatch, Weights},
+};
+
+/// How much a weight is scaled by when trying it out, coarse to fine.
+const STEPS: [f32; 4] = [2f32, 1.4, 1.15, 1.05];
+
+/// The most passes over every weight at one step.
+const MAX_PASSES: usize = 8;
+
+/// How well the weights tell each query's level apart from the rest. Compares
+/// by accuracy first.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Fit {
+    /// the fraction of queries whose own level is the closest
+    pub top1: f32,
+    /// how much closer a query's own level is than the closest other level,
+    /// relative to both distances, from -1 to 1 and averaged over the queries
+    pub margin: f32,
+}
+
+/// Measures how well the set's current weights match the queries. Queries of
+/// levels the set doesn't have are left out.
+///
+/// With `leave_one_out`, each query's own sample is removed from its level
+/// first, like `eval::evaluate` does.
+pub fn fit(levels: &LevelSet, queries: &[Query], mode: SampleMatch, leave_one_out: bool) -> Fit {
+    let measure = levels.measure();
+    let margins = queries
+        .par_iter()
+        .filter_map(|query| {
+            let mut own = None;
+            let mut other = f32::INFINITY;
+            for level in levels.values() {
+                if level.name != query.name {
+                    other = other.min(level.distance_to(&query.coefficients, &measure, mode));
+                } else if leave_one_out {
+                    own = level.distance_excluding(&query.coefficients, &measure, mode);
+                } else {
+                    own = Some(level.distance_to(&query.coefficients, &measure, mode));
+                }
+            }
+
+            let own = own?;
+            if !other.is_finite() {
+                return None;
+            }
+
+            Some(if own + other > 0f32 {
+                (other - own) / (other + own)
+            } else {
+                0f32
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let n = margins.len().max(1) as f32;
+    Fit {
+        top1: margins.iter().filter(|margin| **margin > 0f32).count() as f32 / n,
+        margin: margins.iter().sum::<f32>() / n,
+    }
+}
+
+/// The `i`th tunable weight: the coefficients, then the channels.
+fn weight(weights: &mut Weights, i: usize) -> &mut f32 {
+    let n = weights.coefficients.len();
+    if i < n {
+        &mut weights.coefficients[i]
+    } else {
+        &mut weights.channels[i - n]
+    }
+}
+
+/// Fits the set's weights to the queries by coordinate search, starting from
+/// the set's current ones: each weight in turn is scaled up and down and kept
+/// wherever the fit improves, with finer steps once it stops improving.
+///
+/// Leaves the best weights on the set and returns their fit. `None` if the
+/// fingerprint's distance can't be weighted.
+pub fn tune(
+    levels: &mut LevelSet,
+    queries: &[Query],
+    mode: SampleMatch,
+    leave_one_out: bool,
+) -> Option<Fit> {
+    let mut best = match &levels.weights {
+        Some(weights) => Weights::clone(weights),
+        None => Weights::default_for(levels.fingerprint)?,
+    };
+    levels.weights = Some(Arc::new(best.clone()));
+    let mut best_fit = fit(levels, queries, mode, leave_one_out);
+
+    let count = best.coefficients.len() + best.channels.len();
+    for step in STEPS {
+        for pass in 1..=MAX_PASSES {
+            let mut improved = false;
+            for i in 0..count {
+                for factor in [step, 1f32 / step] {
+                    let mut candidate = best.clone();
+                    *weight(&mut candidate, i) *= factor;
+
+                    levels.weights = Some(Arc::new(candidate.clone()));
+                    let candidate_fit = fit(levels, queries, mode, leave_one_out);
+                    if candidate_fit > best_fit {
+                        best = candidate;
+                        best_fit = candidate_fit;
+                        improved = true;
+                        break;
+                    }
+                }
+            }
+
+            println!(
+                "  step x{} pass {}: top-1 {:.1}%, margin {:.4}",
+                step,
+                pass,
+                best_fit.top1 * 100f32,
+                best_fit.margin
+            );
+            if !improved {
+                break;
+            }
+        }
+    }
+
+    // only relative weights matter to the ranking, so keep the channels
+    // summing to 1 like the built-in average and the largest coefficient at 1,
+    // to keep the saved weights readable. Any change to the weights, this one
+    // included, changes the distances' scale, so the threshold is learned again
+    // for them (the bot learns it when it loads the weights anyway).
+    let total = best.channels.iter().sum::<f32>();
+    best.channels = best.channels.map(|channel| channel / total);
+    let largest = best.coefficients.iter().copied().fold(0f32, f32::max);
+    for coefficient in &mut best.coefficients {
+        *coefficient /= largest;
+    }
+
+    levels.weights = Some(Arc::new(best));
+    levels.threshold = confidence::learn_threshold(levels, mode);
+    levels.unthresholded = 0;
+    Some(best_fit)
+}
@@ -1,15 +1,14 @@
 use std::{collections::HashMap, sync::Arc};
 
 use axum::{
-    extract::{Multipart, Path, State},
+    extract::{Multipart, Path, Query, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     routing::post,
     Json,
 };
 use bytes::Bytes;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use socketioxide::{
     extract::{Data, SocketRef},
@@ -23,9 +22,11 @@ use tower_http::{
 };
 
 use crate::{
-    handler::{LevelDatabase, DCT_PLAN},
-    level::{Coefficients, LevelDifficulty},
-    CHANNELS,
+    confidence::Confidence,
+    game::LevelDatabase,
+    level::LevelDifficulty,
+    search::{self, DifficultyMatch, Guess},
+    CHANNELS, CONFIG,
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -37,10 +38,19 @@ pub enum WebMessage {
     GuessStart {
         channel_id: String,
         difficulty: LevelDifficulty,
+        /// false if the embed didn't say, in which case `GuessData` has the
+        /// difficulty with the closest match
+        difficulty_known: bool,
     },
     GuessData {
         channel_id: String,
-        guess: Option<(String, f32)>,
+        /// the difficulty that was searched
+        difficulty: LevelDifficulty,
+        /// our closest guesses, closest first
+        guesses: Vec<Guess>,
+        confidence: Option<Confidence>,
+        /// a more convincing match from another difficulty
+        elsewhere: Option<DifficultyMatch>,
     },
     GuessWin {
         channel_id: String,
@@ -50,6 +60,11 @@ pub enum WebMessage {
     GuessTimeout {
         channel_id: String,
     },
+    /// the screenshot couldn't be downloaded or read, so there's no guess
+    GuessFailed {
+        channel_id: String,
+        reason: String,
+    },
 }
 
 impl WebMessage {
@@ -60,6 +75,7 @@ impl WebMessage {
             Self::GuessData { .. } => "guess/data",
             Self::GuessWin { .. } => "guess/win",
             Self::GuessTimeout { .. } => "guess/timeout",
+            Self::GuessFailed { .. } => "guess/failed",
         }
     }
 
@@ -96,9 +112,20 @@ async fn get_multipart_bytes(
     anyhow::bail!("field {} not found", field_name)
 }
 
+/// The most guesses `api_guess` returns.
+const MAX_K: usize = 100;
+
+#[derive(Deserialize)]
+struct GuessParams {
+    /// how many guesses to return, `top_k` from the config if unset, at most
+    /// `MAX_K`
+    k: Option<usize>,
+}
+
 async fn api_guess(
     State(state): State<Arc<AppState>>,
     Path(difficulty): Path<String>,
+    Query(params): Query<GuessParams>,
     mut multipart: Multipart,
 ) -> Result<Json<Value>, AppError> {
     let difficulty: LevelDifficulty = difficulty
@@ -106,22 +133,31 @@ async fn api_guess(
         .map_err(|_| anyhow::anyhow!("failed to parse level"))?;
 
     let data = get_multipart_bytes(&mut multipart, "data").await?;
-    let coefficients = Coefficients::new(&data, DCT_PLAN.to_owned())?;
+    let fingerprint = state
+        .database
+        .get(&difficulty)
+        .unwrap()
+        .read()
+        .await
+        .fingerprint;
+    let coefficients = fingerprint.compute(&data)?;
 
     let level_state = state.database.get(&difficulty).unwrap().read().await;
-    let mut guesses = level_state
-        .par_iter()
-        .map(|(_, level)| (level, level.euclidean_distance_to(&coefficients)))
-        .collect::<Vec<_>>();
-
-    guesses.sort_by(|(_, a), (_, b)| a.total_cmp(b));
-    let (level, dist) = guesses
-        .first()
+    let result = search::search(
+        &level_state,
+        &coefficients,
+        params.k.unwrap_or(CONFIG.top_k).clamp(1, MAX_K),
+        CONFIG.sample_match,
+    );
+    let best = result
+        .best()
         .ok_or(anyhow::anyhow!("no guesses for this difficulty"))?;
 
     Ok(Json(json!({
-        "level": level.name.to_owned(),
-        "distance": dist,
+        "level": best.name,
+        "distance": best.distance,
+        "guesses": result.guesses,
+        "confidence": result.confidence,
     })))
 }
 